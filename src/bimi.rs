@@ -0,0 +1,460 @@
+use crate::{Icon, IconInfo, IconKind, IconSource, SiteIcons, CLIENT};
+use futures::{
+  future::{self, FutureExt, LocalBoxFuture},
+  StreamExt,
+};
+use reqwest::header::ACCEPT;
+use scraper::{ElementRef, Html};
+use serde::Deserialize;
+use std::{collections::HashMap, error::Error};
+use tldextract::TldOption;
+use url::Url;
+use xml5ever::{driver, tendril::TendrilSink};
+
+/// The largest logo BIMI allows
+const MAX_SVG_BYTES: usize = 32 * 1024;
+
+/// Elements that aren't allowed in the SVG Tiny Portable/Secure profile
+const FORBIDDEN_SVG_ELEMENTS: &[&str] = &[
+  "script",
+  "foreignObject",
+  "image",
+  "video",
+  "audio",
+  "animate",
+  "animateColor",
+  "animateMotion",
+  "animateTransform",
+  "set",
+];
+
+/// Looks up DNS TXT records, see [`SiteIcons::with_dns_resolver`]
+pub trait DnsResolver {
+  /// The TXT records at `name`, with each record's strings concatenated.
+  /// A name without records resolves to an empty list.
+  fn txt_records<'a>(
+    &'a self,
+    name: &'a str,
+  ) -> LocalBoxFuture<'a, Result<Vec<String>, Box<dyn Error>>>;
+}
+
+/// Resolves TXT records with [DNS-over-HTTPS](https://developers.cloudflare.com/1.1.1.1/encryption/dns-over-https/make-api-requests/dns-json/)
+/// JSON requests, so it works without a system resolver (e.g. on WASM)
+#[derive(Debug, Clone)]
+pub struct DohResolver {
+  endpoint: Url,
+}
+
+impl DohResolver {
+  pub fn new(endpoint: Url) -> Self {
+    DohResolver { endpoint }
+  }
+}
+
+impl Default for DohResolver {
+  fn default() -> Self {
+    DohResolver::new(Url::parse("https://cloudflare-dns.com/dns-query").unwrap())
+  }
+}
+
+#[derive(Debug, Deserialize)]
+struct DohResponse {
+  #[serde(rename = "Status")]
+  status: u16,
+  #[serde(rename = "Answer", default)]
+  answer: Vec<DohAnswer>,
+}
+
+#[derive(Debug, Deserialize)]
+struct DohAnswer {
+  #[serde(rename = "type")]
+  record_type: u16,
+  data: String,
+}
+
+/// NXDOMAIN
+const DNS_NAME_ERROR: u16 = 3;
+const DNS_TXT_RECORD: u16 = 16;
+
+impl DnsResolver for DohResolver {
+  fn txt_records<'a>(
+    &'a self,
+    name: &'a str,
+  ) -> LocalBoxFuture<'a, Result<Vec<String>, Box<dyn Error>>> {
+    async move {
+      let mut url = self.endpoint.clone();
+      url
+        .query_pairs_mut()
+        .append_pair("name", name)
+        .append_pair("type", "TXT");
+
+      let res: DohResponse = CLIENT
+        .get(url)
+        .header(ACCEPT, "application/dns-json")
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+
+      match res.status {
+        0 | DNS_NAME_ERROR => {}
+        status => return Err(format!("{}: DNS error {}", name, status).into()),
+      }
+
+      Ok(
+        res
+          .answer
+          .into_iter()
+          .filter(|answer| answer.record_type == DNS_TXT_RECORD)
+          .map(|answer| join_txt_strings(&answer.data))
+          .collect(),
+      )
+    }
+    .boxed_local()
+  }
+}
+
+/// An in-memory resolver, mapping names to their TXT records
+impl DnsResolver for HashMap<String, Vec<String>> {
+  fn txt_records<'a>(
+    &'a self,
+    name: &'a str,
+  ) -> LocalBoxFuture<'a, Result<Vec<String>, Box<dyn Error>>> {
+    future::ready(Ok(self.get(name).cloned().unwrap_or_default())).boxed_local()
+  }
+}
+
+/// TXT data is presented as one or more quoted strings, e.g. `"v=BIMI1; " "l=..."`
+fn join_txt_strings(data: &str) -> String {
+  if !data.trim_start().starts_with('"') {
+    return data.to_string();
+  }
+
+  let mut joined = String::new();
+  let mut chars = data.chars();
+  let mut in_string = false;
+
+  while let Some(c) = chars.next() {
+    match c {
+      '"' => in_string = !in_string,
+      '\\' if in_string => joined.extend(chars.next()),
+      c if in_string => joined.push(c),
+      _ => {}
+    }
+  }
+
+  joined
+}
+
+/// A [BIMI](https://bimigroup.org/) DNS record
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BimiRecord {
+  /// The `l=` logo location, `None` when the domain declined to publish one
+  pub location: Option<Url>,
+  /// The `a=` authority evidence (Verified Mark Certificate) location
+  pub authority: Option<Url>,
+}
+
+impl BimiRecord {
+  /// Parses a `v=BIMI1; l=https://...; a=https://...` record
+  pub fn parse(record: &str) -> Option<BimiRecord> {
+    let mut tags = record
+      .split(';')
+      .map(|tag| tag.trim())
+      .filter(|tag| !tag.is_empty())
+      .filter_map(|tag| tag.split_once('='))
+      .map(|(name, value)| (name.trim(), value.trim()));
+
+    // the version has to be the first tag
+    match tags.next() {
+      Some((name, "BIMI1")) if name.eq_ignore_ascii_case("v") => {}
+      _ => return None,
+    }
+
+    let mut record = BimiRecord {
+      location: None,
+      authority: None,
+    };
+
+    for (name, value) in tags {
+      let url = Url::parse(value).ok().filter(|url| url.scheme() == "https");
+
+      match name.to_ascii_lowercase().as_str() {
+        "l" => record.location = url,
+        "a" => record.authority = url,
+        _ => {}
+      }
+    }
+
+    Some(record)
+  }
+}
+
+/// Checks that an SVG conforms to the SVG Tiny Portable/Secure profile BIMI
+/// requires. Returns the reasons it doesn't.
+pub fn validate_svg_tiny_ps(svg: &str) -> Result<(), Vec<String>> {
+  let document = driver::parse_document(Html::new_document(), Default::default()).one(svg);
+  let mut errors = Vec::new();
+
+  let root = match document.tree.root().children().find_map(ElementRef::wrap) {
+    Some(root) => root,
+    None => return Err(vec!["not an XML document".to_string()]),
+  };
+  let root_elem = root.value();
+
+  if root_elem.name() != "svg" {
+    errors.push(format!("root element is <{}>, not <svg>", root_elem.name()));
+  }
+  if root_elem.attr("baseProfile") != Some("tiny-ps") {
+    errors.push("baseProfile isn't tiny-ps".to_string());
+  }
+  if root_elem.attr("version") != Some("1.2") {
+    errors.push("version isn't 1.2".to_string());
+  }
+  if root_elem.attr("x").is_some() || root_elem.attr("y").is_some() {
+    errors.push("root <svg> has x or y attributes".to_string());
+  }
+
+  let elements = root
+    .descendants()
+    .filter_map(|node| node.value().as_element())
+    .collect::<Vec<_>>();
+
+  if !elements.iter().any(|elem| elem.name() == "title") {
+    errors.push("missing <title>".to_string());
+  }
+
+  for elem in &elements {
+    if FORBIDDEN_SVG_ELEMENTS.contains(&elem.name()) {
+      errors.push(format!("<{}> isn't allowed", elem.name()));
+    }
+
+    // xlink:href and href, only references within the document are allowed
+    if let Some((_, href)) = elem
+      .attrs()
+      .find(|(name, value)| *name == "href" && !value.trim().starts_with('#'))
+    {
+      errors.push(format!("external reference to {}", href));
+    }
+  }
+
+  if errors.is_empty() {
+    Ok(())
+  } else {
+    Err(errors)
+  }
+}
+
+impl SiteIcons {
+  /// Loads the [BIMI](https://bimigroup.org/) brand logo of an email domain,
+  /// from the `default._bimi` TXT record of the domain (or its organizational
+  /// domain). The logo has to be a valid SVG Tiny PS document.
+  pub async fn load_bimi(&self, domain: &str) -> Result<Icon, Box<dyn Error>> {
+    let domain = domain.trim().trim_end_matches('.').to_ascii_lowercase();
+
+    let url = self
+      .bimi_record(&domain)
+      .await?
+      .ok_or_else(|| format!("{}: no BIMI record", domain))?
+      .location
+      .ok_or_else(|| format!("{}: BIMI record has no logo", domain))?;
+
+    if self.is_blacklisted(&url) {
+      return Err(format!("{}: blacklisted", url).into());
+    }
+
+    let res = CLIENT.get(url.clone()).send().await?.error_for_status()?;
+
+    let mut svg = Vec::new();
+    let mut body = res.bytes_stream();
+    while let Some(data) = body.next().await {
+      svg.extend_from_slice(&data?);
+
+      if svg.len() > MAX_SVG_BYTES {
+        return Err(format!("{}: logo is larger than 32 KiB", url).into());
+      }
+    }
+
+    validate_svg_tiny_ps(&String::from_utf8_lossy(&svg))
+      .map_err(|errors| format!("{}: not SVG Tiny PS: {}", url, errors.join(", ")))?;
+
+    let mut icon = Icon::new(url, IconKind::SiteLogo, IconInfo::from_svg(&svg).await?);
+    icon.source = Some(IconSource::Bimi);
+
+    Ok(icon)
+  }
+
+  /// The BIMI record of an email domain, falling back to the record of its
+  /// organizational domain (e.g. `example.com` for `mail.example.com`)
+  async fn bimi_record(&self, domain: &str) -> Result<Option<BimiRecord>, Box<dyn Error>> {
+    let mut domains = vec![domain.to_string()];
+    if let Ok(extracted) = TldOption::default().build().extract(domain) {
+      if let (Some(name), Some(suffix)) = (extracted.domain, extracted.suffix) {
+        domains.push(format!("{}.{}", name, suffix));
+      }
+    }
+    domains.dedup();
+
+    for domain in &domains {
+      let name = format!("default._bimi.{}", domain);
+
+      let record = self
+        .dns_resolver
+        .txt_records(&name)
+        .await?
+        .iter()
+        .find_map(|record| BimiRecord::parse(record));
+
+      if record.is_some() {
+        return Ok(record);
+      }
+    }
+
+    Ok(None)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn parses_records() {
+    let record =
+      BimiRecord::parse("v=BIMI1; l=https://example.com/logo.svg; a=https://example.com/vmc.pem")
+        .unwrap();
+    assert_eq!(
+      record.location.as_ref().map(Url::as_str),
+      Some("https://example.com/logo.svg")
+    );
+    assert_eq!(
+      record.authority.as_ref().map(Url::as_str),
+      Some("https://example.com/vmc.pem")
+    );
+
+    // a declined record, with case-insensitive tag names
+    let record = BimiRecord::parse("V=BIMI1; L=;").unwrap();
+    assert_eq!(record.location, None);
+
+    // the logo has to be served over https
+    let record = BimiRecord::parse("v=BIMI1; l=http://example.com/logo.svg").unwrap();
+    assert_eq!(record.location, None);
+
+    assert_eq!(
+      BimiRecord::parse("l=https://example.com/logo.svg; v=BIMI1"),
+      None
+    );
+    assert_eq!(BimiRecord::parse("v=spf1 include:example.com ~all"), None);
+  }
+
+  #[test]
+  fn joins_txt_strings() {
+    assert_eq!(
+      join_txt_strings(r#""v=BIMI1; " "l=https://example.com/logo.svg""#),
+      "v=BIMI1; l=https://example.com/logo.svg"
+    );
+    assert_eq!(
+      join_txt_strings(r#""a \"quoted\" value""#),
+      r#"a "quoted" value"#
+    );
+    assert_eq!(join_txt_strings("v=BIMI1; l="), "v=BIMI1; l=");
+  }
+
+  #[test]
+  fn rejects_documents_without_root() {
+    for svg in ["", "Not Found", "<!-- -->"] {
+      assert_eq!(
+        validate_svg_tiny_ps(svg),
+        Err(vec!["not an XML document".to_string()])
+      );
+    }
+
+    assert_eq!(
+      validate_svg_tiny_ps("<html><body>Not Found</body></html>").unwrap_err()[0],
+      "root element is <html>, not <svg>"
+    );
+  }
+
+  #[test]
+  fn validates_svg_tiny_ps() {
+    let valid = r##"<svg xmlns="http://www.w3.org/2000/svg" version="1.2" baseProfile="tiny-ps" viewBox="0 0 10 10"><title>Example</title><use href="#a"/><rect id="a" width="10" height="10"/></svg>"##;
+    assert_eq!(validate_svg_tiny_ps(valid), Ok(()));
+
+    let errors = validate_svg_tiny_ps(
+      r#"<svg xmlns="http://www.w3.org/2000/svg" xmlns:xlink="http://www.w3.org/1999/xlink" x="0" viewBox="0 0 10 10"><script>alert(1)</script><use xlink:href="https://example.com/a.svg#a"/></svg>"#,
+    )
+    .unwrap_err();
+    assert_eq!(
+      errors,
+      vec![
+        "baseProfile isn't tiny-ps",
+        "version isn't 1.2",
+        "root <svg> has x or y attributes",
+        "missing <title>",
+        "<script> isn't allowed",
+        "external reference to https://example.com/a.svg#a",
+      ]
+    );
+  }
+
+  fn resolver(records: &[(&str, &str)]) -> HashMap<String, Vec<String>> {
+    records
+      .iter()
+      .map(|(name, record)| (name.to_string(), vec![record.to_string()]))
+      .collect()
+  }
+
+  #[tokio::test]
+  async fn falls_back_to_the_organizational_domain() {
+    let icons = SiteIcons::new().with_dns_resolver(resolver(&[(
+      "default._bimi.example.co.uk",
+      "v=BIMI1; l=https://example.co.uk/logo.svg",
+    )]));
+
+    let record = icons
+      .bimi_record("mail.example.co.uk")
+      .await
+      .unwrap()
+      .unwrap();
+    assert_eq!(
+      record.location.as_ref().map(Url::as_str),
+      Some("https://example.co.uk/logo.svg")
+    );
+
+    assert_eq!(icons.bimi_record("example.com").await.unwrap(), None);
+  }
+
+  #[tokio::test]
+  async fn prefers_the_subdomain_record() {
+    let icons = SiteIcons::new().with_dns_resolver(resolver(&[
+      (
+        "default._bimi.mail.example.com",
+        "v=BIMI1; l=https://example.com/mail.svg",
+      ),
+      (
+        "default._bimi.example.com",
+        "v=BIMI1; l=https://example.com/logo.svg",
+      ),
+    ]));
+
+    let record = icons
+      .bimi_record("mail.example.com")
+      .await
+      .unwrap()
+      .unwrap();
+    assert_eq!(
+      record.location.as_ref().map(Url::as_str),
+      Some("https://example.com/mail.svg")
+    );
+  }
+
+  #[tokio::test]
+  async fn reports_declined_records() {
+    let icons =
+      SiteIcons::new().with_dns_resolver(resolver(&[("default._bimi.example.com", "v=BIMI1; l=")]));
+
+    let err = icons.load_bimi("Example.com.").await.unwrap_err();
+    assert_eq!(err.to_string(), "example.com: BIMI record has no logo");
+  }
+}
@@ -0,0 +1,130 @@
+use crate::{
+  feed::{self, FeedKind},
+  html_parser, Icon, IconKind, IconPurpose, IconSizes, IconSource, LogoHeuristics, Manifest,
+  NativeApp, ScoredLogo,
+};
+use itertools::Itertools;
+use scraper::Html;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use url::Url;
+
+/// An icon declared by a site, that hasn't been loaded yet
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct IconCandidate {
+  /// The icon URL, resolved against the page (or manifest) URL
+  pub href: Url,
+  pub kind: IconKind,
+  /// The `rel` of the `<link>` tag or `Link` header that declared the icon
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub rel: Option<String>,
+  /// The declared `sizes`, which aren't necessarily the real ones
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub sizes: Option<String>,
+  #[serde(rename = "type", default, skip_serializing_if = "Option::is_none")]
+  pub mime_type: Option<String>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub media: Option<String>,
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  pub purpose: Vec<IconPurpose>,
+  pub source: IconSource,
+}
+
+/// The icons, manifests, feeds and OpenSearch descriptions declared by the
+/// `<link>` tags of a page
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HeadCandidates {
+  pub icons: Vec<IconCandidate>,
+  pub manifests: Vec<Url>,
+  pub feeds: Vec<Url>,
+  pub opensearch: Vec<Url>,
+  /// Apps from smart app banner `<meta>` tags
+  pub apps: Vec<NativeApp>,
+}
+
+impl IconCandidate {
+  /// Parses the `<link>` tags of an HTML document, without fetching anything.
+  /// Links found outside of the head are included with [`IconSource::BodyTag`].
+  pub fn parse_head(url: &Url, html: &str) -> HeadCandidates {
+    let document = Html::parse_document(html);
+    let links = html_parser::parse_head_links(&document);
+    let feeds = |kind| {
+      links
+        .iter()
+        .filter_map(|link| link.feed_url(url))
+        .filter(|(_, feed_kind)| *feed_kind == kind)
+        .map(|(url, _)| url)
+        .unique()
+        .collect()
+    };
+
+    HeadCandidates {
+      icons: links
+        .iter()
+        .filter_map(|link| link.icon_candidate(url))
+        .collect(),
+      manifests: links
+        .iter()
+        .filter_map(|link| link.manifest_url(url))
+        .unique()
+        .collect(),
+      feeds: feeds(FeedKind::Feed),
+      opensearch: feeds(FeedKind::OpenSearch),
+      apps: html_parser::parse_native_apps(&document),
+    }
+  }
+
+  /// Parses the icons of a web app manifest, without fetching anything
+  pub fn parse_manifest(url: &Url, json: &[u8]) -> Result<Vec<IconCandidate>, serde_json::Error> {
+    let (manifest, _) = Manifest::parse(json)?;
+
+    Ok(manifest.icon_candidates(url))
+  }
+
+  /// Parses the channel image of an RSS feed, or the icon and logo of an Atom feed
+  pub fn parse_feed(url: &Url, xml: &str) -> Vec<IconCandidate> {
+    feed::parse_feed(url, xml)
+  }
+
+  /// Parses the images of an OpenSearch description
+  pub fn parse_opensearch(url: &Url, xml: &str) -> Vec<IconCandidate> {
+    feed::parse_opensearch(url, xml)
+  }
+
+  /// Finds the site logos on a page, without fetching anything.
+  /// They're sorted from most to least likely to be the actual logo.
+  pub fn parse_site_logos(url: &Url, html: &str) -> Vec<IconCandidate> {
+    IconCandidate::parse_site_logos_with(url, html, &LogoHeuristics::default())
+  }
+
+  /// Like [`IconCandidate::parse_site_logos`], with custom heuristics
+  pub fn parse_site_logos_with(
+    url: &Url,
+    html: &str,
+    heuristics: &LogoHeuristics,
+  ) -> Vec<IconCandidate> {
+    html_parser::site_logo_candidates(&Html::parse_document(html), url, heuristics, &[], |_| false)
+  }
+
+  /// Scores the site logos on a page, with the rules that applied to each.
+  /// Unlike [`IconCandidate::parse_site_logos_with`], this shows why a logo
+  /// was picked over the others.
+  pub fn score_site_logos(url: &Url, html: &str, heuristics: &LogoHeuristics) -> Vec<ScoredLogo> {
+    html_parser::score_site_logos(&Html::parse_document(html), url, heuristics, &[], |_| false)
+  }
+
+  /// Whether the icon was declared with `sizes="any"`, e.g. a scalable SVG
+  pub fn is_scalable(&self) -> bool {
+    self.sizes.as_deref().is_some_and(IconSizes::contains_any)
+  }
+
+  /// Loads the icon, to find its real type and size
+  pub async fn resolve(self) -> Result<Icon, Box<dyn Error>> {
+    let mut icon = Icon::load(self.href, self.kind, self.sizes).await?;
+    icon.purpose = self.purpose;
+    icon.source = Some(self.source);
+    icon.media = self.media;
+
+    Ok(icon)
+  }
+}
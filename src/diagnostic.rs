@@ -0,0 +1,20 @@
+use crate::ManifestRepair;
+use serde::{Deserialize, Serialize};
+use std::fmt::{self, Display};
+use url::Url;
+
+/// A problem that was worked around while loading a site
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Diagnostic {
+  /// The manifest at `url` had to be repaired before it could be parsed
+  ManifestRepaired { url: Url, repair: ManifestRepair },
+}
+
+impl Display for Diagnostic {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Diagnostic::ManifestRepaired { url, repair } => write!(f, "{}: {}", url, repair),
+    }
+  }
+}
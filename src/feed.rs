@@ -0,0 +1,201 @@
+use crate::{IconCandidate, IconKind, IconSource, SiteIcons, CLIENT};
+use cached::proc_macro::cached;
+use futures::StreamExt;
+use scraper::{ElementRef, Html};
+use std::error::Error;
+use url::Url;
+use xml5ever::{driver, tendril::TendrilSink};
+
+/// How much of a feed or OpenSearch description is read. The images are
+/// declared before the feed entries, so large feeds are cut off.
+const MAX_FEED_BYTES: usize = 64 * 1024;
+
+/// An XML document linked from the head, that can declare site images
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FeedKind {
+  /// A `rel=alternate` RSS or Atom feed
+  Feed,
+  /// A `rel=search` [OpenSearch](https://github.com/dewitt/opensearch) description
+  OpenSearch,
+}
+
+fn parse_xml(xml: &str) -> Html {
+  driver::parse_document(Html::new_document(), Default::default()).one(xml)
+}
+
+fn text(elem_ref: ElementRef<'_>) -> Option<String> {
+  let text = elem_ref.text().collect::<String>();
+  let text = text.trim();
+
+  if text.is_empty() {
+    None
+  } else {
+    Some(text.to_string())
+  }
+}
+
+fn declared_sizes(width: Option<String>, height: Option<String>) -> Option<String> {
+  let width = width?.trim().parse::<u32>().ok()?;
+  let height = height?.trim().parse::<u32>().ok()?;
+
+  Some(format!("{}x{}", width, height))
+}
+
+fn candidate(
+  url: &Url,
+  href: &str,
+  kind: IconKind,
+  sizes: Option<String>,
+  mime_type: Option<String>,
+  source: &IconSource,
+) -> Option<IconCandidate> {
+  Some(IconCandidate {
+    href: url.join(href.trim()).ok()?,
+    kind,
+    rel: None,
+    sizes,
+    mime_type,
+    media: None,
+    purpose: Vec::new(),
+    source: source.clone(),
+  })
+}
+
+/// The channel image of an RSS feed, or the `<icon>` and `<logo>` of an Atom feed
+pub fn parse_feed(url: &Url, xml: &str) -> Vec<IconCandidate> {
+  let document = parse_xml(xml);
+  let source = IconSource::Feed(url.clone());
+  let mut candidates = Vec::new();
+
+  // RSS 2.0 puts the <image> inside the <channel>, RSS 1.0 next to it
+  for image in document.select(selector!("rss > channel > image", "RDF > image")) {
+    let child = |name| {
+      image
+        .children()
+        .filter_map(ElementRef::wrap)
+        .find(|child| child.value().name() == name)
+        .and_then(text)
+    };
+
+    if let Some(href) = child("url") {
+      let sizes = declared_sizes(child("width"), child("height"));
+      candidates.extend(candidate(
+        url,
+        &href,
+        IconKind::SiteLogo,
+        sizes,
+        None,
+        &source,
+      ));
+    }
+  }
+
+  // e.g. <itunes:image href="..."> on podcast feeds
+  for image in document.select(selector!("rss > channel > image[href]")) {
+    if let Some(href) = image.value().attr("href") {
+      candidates.extend(candidate(
+        url,
+        href,
+        IconKind::SiteLogo,
+        None,
+        None,
+        &source,
+      ));
+    }
+  }
+
+  for (selector, kind) in [
+    (selector!("feed > icon"), IconKind::SiteFavicon),
+    (selector!("feed > logo"), IconKind::SiteLogo),
+  ] {
+    for elem_ref in document.select(selector) {
+      if let Some(href) = text(elem_ref) {
+        candidates.extend(candidate(url, &href, kind.clone(), None, None, &source));
+      }
+    }
+  }
+
+  candidates
+}
+
+/// The `<Image>` elements of an OpenSearch description
+pub fn parse_opensearch(url: &Url, xml: &str) -> Vec<IconCandidate> {
+  let document = parse_xml(xml);
+  let source = IconSource::OpenSearch(url.clone());
+
+  document
+    .select(selector!("OpenSearchDescription > Image"))
+    .filter_map(|image| {
+      let attr = |name| image.value().attr(name).map(String::from);
+
+      candidate(
+        url,
+        &text(image)?,
+        IconKind::SiteFavicon,
+        declared_sizes(attr("width"), attr("height")),
+        attr("type"),
+        &source,
+      )
+    })
+    .collect()
+}
+
+impl SiteIcons {
+  pub(crate) async fn discover_feed(
+    url: Url,
+    kind: FeedKind,
+  ) -> Result<Vec<IconCandidate>, Box<dyn Error>> {
+    Ok(discover_feed_cached(url, kind).await?)
+  }
+}
+
+#[cached(sync_writes = true)]
+async fn discover_feed_cached(url: Url, kind: FeedKind) -> Result<Vec<IconCandidate>, String> {
+  let url = &url;
+
+  let res = CLIENT
+    .get(url.clone())
+    .send()
+    .await
+    .map_err(|e| format!("{}: {:?}", url, e))?
+    .error_for_status()
+    .map_err(|e| format!("{}: {:?}", url, e))?;
+
+  let mut xml = Vec::new();
+  let mut body = res.bytes_stream();
+  while let Some(data) = body.next().await {
+    let data = data.map_err(|e| format!("{}: {:?}", url, e))?;
+    xml.extend_from_slice(&data[..data.len().min(MAX_FEED_BYTES - xml.len())]);
+
+    if xml.len() == MAX_FEED_BYTES {
+      break;
+    }
+  }
+
+  let body = String::from_utf8_lossy(&xml);
+
+  Ok(match kind {
+    FeedKind::Feed => parse_feed(url, &body),
+    FeedKind::OpenSearch => parse_opensearch(url, &body),
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn parses_truncated_feeds() {
+    let url = Url::parse("https://example.com/feed.xml").unwrap();
+    let xml = r#"<?xml version="1.0"?>
+      <rss version="2.0"><channel>
+        <image><url>/logo.png</url><width>144</width><height>48</height></image>
+        <item><title>Cut off"#;
+
+    let candidates = parse_feed(&url, xml);
+    assert_eq!(candidates.len(), 1);
+    assert_eq!(candidates[0].href.as_str(), "https://example.com/logo.png");
+    assert_eq!(candidates[0].sizes.as_deref(), Some("144x48"));
+    assert_eq!(candidates[0].source, IconSource::Feed(url));
+  }
+}
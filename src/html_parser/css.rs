@@ -0,0 +1,205 @@
+use crate::CLIENT;
+use futures::StreamExt;
+use scraper::{Html, Selector};
+use url::Url;
+
+/// A stylesheet rule
+#[derive(Debug, Clone)]
+pub struct StyleRule {
+  /// The selector, without its `::before` / `::after` pseudo-element
+  pub selector: Selector,
+  /// Whether the rule styles a `::before` / `::after` pseudo-element
+  pub pseudo_element: bool,
+  pub declarations: String,
+  /// The URL of the stylesheet, that `url()`s are relative to
+  pub base: Url,
+}
+
+impl StyleRule {
+  pub fn background_image(&self) -> Option<Url> {
+    background_image_url(&self.declarations, &self.base)
+  }
+}
+
+/// The first `url()` of the `background` / `background-image` declarations
+/// in a declaration block, e.g. an inline `style` attribute
+pub fn background_image_url(declarations: &str, base: &Url) -> Option<Url> {
+  regex!(r"(?i)(?:^|;)\s*background(?:-image)?\s*:([^;]*)")
+    .captures_iter(declarations)
+    .filter_map(|captures| {
+      let captures = regex!(r#"(?i)url\(\s*(?:"([^"]*)"|'([^']*)'|([^)'"\s]*))\s*\)"#)
+        .captures(captures.get(1)?.as_str())?;
+
+      let href = captures
+        .get(1)
+        .or_else(|| captures.get(2))
+        .or_else(|| captures.get(3))?
+        .as_str()
+        .trim();
+
+      if href.is_empty() {
+        None
+      } else {
+        base.join(href).ok()
+      }
+    })
+    // later declarations override earlier ones
+    .last()
+}
+
+/// Parses the rules of a stylesheet, including the ones nested in `@media` /
+/// `@supports` blocks. Rules with selectors scraper can't match are left out.
+pub fn parse_style_rules(css: &str, base: &Url) -> Vec<StyleRule> {
+  let css = regex!(r"(?s)/\*.*?\*/").replace_all(css, "");
+
+  regex!(r"([^{}]+)\{([^{}]*)\}")
+    .captures_iter(&css)
+    .filter_map(|captures| {
+      // e.g. `@import url(...);` or `@charset "utf-8";` before the selector
+      let selector = captures[1].rsplit(';').next()?.trim();
+      if selector.starts_with('@') {
+        return None;
+      }
+
+      let pseudo_element = regex!(r"::?(?i:before|after)\b");
+      let selector_without_pseudo = pseudo_element.replace_all(selector, "");
+
+      Some(StyleRule {
+        selector: Selector::parse(&selector_without_pseudo).ok()?,
+        pseudo_element: pseudo_element.is_match(selector),
+        declarations: captures[2].trim().to_string(),
+        base: base.clone(),
+      })
+    })
+    .collect()
+}
+
+/// The rules of the page's `<style>` blocks
+pub fn parse_style_blocks(document: &Html, url: &Url) -> Vec<StyleRule> {
+  document
+    .select(selector!("style"))
+    .flat_map(|style| parse_style_rules(&style.text().collect::<String>(), url))
+    .collect()
+}
+
+/// The `<link rel=stylesheet>` URLs of the page
+pub fn stylesheet_urls(document: &Html, url: &Url) -> Vec<Url> {
+  document
+    .select(selector!("link[rel~=stylesheet][href]"))
+    .filter(|link| link.value().attr("media").map(str::trim) != Some("print"))
+    .filter_map(|link| url.join(link.value().attr("href")?).ok())
+    .collect()
+}
+
+/// Downloads the stylesheets in order, until `max_bytes` of CSS have been read,
+/// and parses their rules
+pub async fn load_style_rules(urls: Vec<Url>, max_bytes: usize) -> Vec<StyleRule> {
+  let mut rules = Vec::new();
+  let mut remaining = max_bytes;
+
+  for url in urls {
+    if remaining == 0 {
+      break;
+    }
+
+    let res = match CLIENT.get(url.clone()).send().await {
+      Ok(res) if res.status().is_success() => res,
+      Ok(res) => {
+        info!("{}: stylesheet returned {}", url, res.status());
+        continue;
+      }
+      Err(err) => {
+        info!("{}: {}", url, err);
+        continue;
+      }
+    };
+
+    // stylesheets are relative to their own URL
+    let url = res.url().clone();
+    let mut css = Vec::new();
+    let mut body = res.bytes_stream();
+    while let Some(Ok(data)) = body.next().await {
+      let len = data.len().min(remaining);
+      css.extend_from_slice(&data[..len]);
+      remaining -= len;
+
+      if remaining == 0 {
+        break;
+      }
+    }
+
+    rules.extend(parse_style_rules(&String::from_utf8_lossy(&css), &url));
+  }
+
+  rules
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn base() -> Url {
+    Url::parse("https://example.com/css/site.css").unwrap()
+  }
+
+  fn rules(css: &str) -> Vec<(bool, String)> {
+    parse_style_rules(css, &base())
+      .into_iter()
+      .map(|rule| (rule.pseudo_element, rule.declarations))
+      .collect()
+  }
+
+  #[test]
+  fn parses_nested_rules() {
+    assert_eq!(
+      rules(
+        "/* { comment } */
+        @media (min-width: 600px) {
+          .logo { background: url(logo.png) }
+          @supports (display: grid) { .brand::before { content: '' } }
+        }
+        @font-face { font-family: x }
+        .a > b { color: red; }"
+      ),
+      vec![
+        (false, "background: url(logo.png)".to_string()),
+        (true, "content: ''".to_string()),
+        (false, "color: red;".to_string()),
+      ]
+    );
+  }
+
+  #[test]
+  fn parses_rules_after_at_statements() {
+    assert_eq!(
+      rules(
+        r#"@charset "utf-8"; @import url("fonts.css"); .logo { background-image: url(a.svg) }"#
+      ),
+      vec![(false, "background-image: url(a.svg)".to_string())]
+    );
+  }
+
+  #[test]
+  fn skips_unsupported_selectors() {
+    assert_eq!(
+      rules(".a:has(> b) { color: red } .b { color: blue }").len(),
+      1
+    );
+  }
+
+  #[test]
+  fn finds_background_images() {
+    let url = |declarations| background_image_url(declarations, &base()).map(String::from);
+
+    assert_eq!(
+      url("background: #fff url('../img/logo.png') no-repeat").as_deref(),
+      Some("https://example.com/img/logo.png")
+    );
+    assert_eq!(
+      url(r#"background-image: url("a.png"); BACKGROUND-IMAGE: url( b.svg )"#).as_deref(),
+      Some("https://example.com/css/b.svg")
+    );
+    assert_eq!(url("background-color: red; border-image: url(a.png)"), None);
+    assert_eq!(url("background-image: url('')"), None);
+  }
+}
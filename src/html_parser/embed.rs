@@ -0,0 +1,143 @@
+use crate::IconInfo;
+use scraper::ElementRef;
+use url::Url;
+
+/// Whether the embedded resource is an image, from its `type` or else its URL,
+/// as `<object>` and `<embed>` are also used for videos, PDFs etc.
+fn is_image(href: &Url, mime_type: Option<&str>) -> bool {
+  match mime_type
+    .map(str::trim)
+    .filter(|mime_type| !mime_type.is_empty())
+  {
+    Some(mime_type) => {
+      mime_type.to_ascii_lowercase().starts_with("image/") && IconInfo::is_supported_type(mime_type)
+    }
+    None if href.scheme() == "data" => href.path().starts_with("image/"),
+    None => regex!(r"(?i)\.(svgz?|png|gif|jpe?g|ico)$").is_match(href.path()),
+  }
+}
+
+/// The image of an `<object data>` or `<embed src>`, e.g.
+/// `<object type="image/svg+xml" data="logo.svg">`
+pub fn embedded_image_source(elem_ref: ElementRef, base: &Url) -> Option<Url> {
+  let elem = elem_ref.value();
+  let href = match elem.name() {
+    "object" => elem.attr("data")?,
+    "embed" => elem.attr("src")?,
+    _ => return None,
+  };
+
+  let href = base.join(href.trim()).ok()?;
+  if is_image(&href, elem.attr("type")) {
+    Some(href)
+  } else {
+    None
+  }
+}
+
+/// The image an `<svg>` only wraps, e.g. `<svg><image href="logo.png"/></svg>`.
+/// As a data URI the `<svg>` couldn't load it, so the image is used directly.
+pub fn wrapped_svg_image(svg: ElementRef, base: &Url) -> Option<Url> {
+  // an <svg> that draws something itself is kept whole
+  if svg
+    .select(selector!(
+      "path, rect, circle, ellipse, line, polyline, polygon, text, use"
+    ))
+    .next()
+    .is_some()
+  {
+    return None;
+  }
+
+  let mut images = svg.select(selector!("image"));
+  let image = images.next()?;
+  if images.next().is_some() {
+    return None;
+  }
+
+  // `attr` only looks up attributes without a namespace, not `xlink:href`
+  let (_, href) = image
+    .value()
+    .attrs
+    .iter()
+    .find(|(name, _)| &*name.local == "href")?;
+
+  base.join(href.trim()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use scraper::Html;
+
+  fn base() -> Url {
+    Url::parse("https://example.com/").unwrap()
+  }
+
+  fn embedded(html: &str) -> Option<String> {
+    let document = Html::parse_fragment(html);
+    let elem_ref = document.select(selector!("object, embed")).next().unwrap();
+    embedded_image_source(elem_ref, &base()).map(String::from)
+  }
+
+  fn wrapped(html: &str) -> Option<String> {
+    let document = Html::parse_fragment(html);
+    let svg = document.select(selector!("svg")).next().unwrap();
+    wrapped_svg_image(svg, &base()).map(String::from)
+  }
+
+  #[test]
+  fn finds_embedded_images() {
+    assert_eq!(
+      embedded(r#"<object type="image/svg+xml" data="/brand"></object>"#).as_deref(),
+      Some("https://example.com/brand")
+    );
+    assert_eq!(
+      embedded(r#"<embed src=" logo.SVG ">"#).as_deref(),
+      Some("https://example.com/logo.SVG")
+    );
+    assert_eq!(
+      embedded(r#"<object data="data:image/png;base64,iVBORw0KGgo="></object>"#).as_deref(),
+      Some("data:image/png;base64,iVBORw0KGgo=")
+    );
+  }
+
+  #[test]
+  fn skips_other_embeds() {
+    assert_eq!(embedded(r#"<object data="/intro.pdf"></object>"#), None);
+    assert_eq!(embedded(r#"<embed src="/video" type="video/mp4">"#), None);
+    assert_eq!(
+      embedded(r#"<object type="image/avif" data="/logo.avif"></object>"#),
+      None
+    );
+    assert_eq!(embedded(r#"<object type="image/svg+xml"></object>"#), None);
+  }
+
+  #[test]
+  fn finds_wrapped_images() {
+    assert_eq!(
+      wrapped(r#"<svg viewBox="0 0 10 10"><image href="logo.png" width="10"/></svg>"#).as_deref(),
+      Some("https://example.com/logo.png")
+    );
+    assert_eq!(
+      wrapped(
+        r#"<svg xmlns:xlink="http://www.w3.org/1999/xlink"><g><image xlink:href="/a/logo.png"/></g></svg>"#
+      )
+      .as_deref(),
+      Some("https://example.com/a/logo.png")
+    );
+  }
+
+  #[test]
+  fn keeps_svgs_that_draw() {
+    assert_eq!(
+      wrapped(r#"<svg><image href="bg.png"/><path d="M0 0h1"/></svg>"#),
+      None
+    );
+    assert_eq!(
+      wrapped(r#"<svg><image href="a.png"/><image href="b.png"/></svg>"#),
+      None
+    );
+    assert_eq!(wrapped(r#"<svg><rect width="1" height="1"/></svg>"#), None);
+  }
+}
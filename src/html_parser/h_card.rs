@@ -0,0 +1,246 @@
+use itertools::Itertools;
+use scraper::{ElementRef, Html};
+use url::Url;
+
+/// The images of the page's [representative h-card](https://microformats.org/wiki/representative-h-card-parsing),
+/// its `u-logo`s followed by its `u-photo`s.
+pub fn parse_h_card_logos(document: &Html, url: &Url) -> (Vec<Url>, Vec<Url>) {
+  let card = match representative_h_card(document, url) {
+    Some(card) => card,
+    None => return Default::default(),
+  };
+
+  let images = |name| {
+    properties(card, name)
+      .into_iter()
+      .filter_map(|property| url_property(property, url))
+      .unique()
+      .collect::<Vec<_>>()
+  };
+
+  let mut photos = images("u-photo");
+  if photos.is_empty() && !has_explicit_properties(card, &["u-", "e-"]) {
+    photos.extend(implied_photo(card, url));
+  }
+
+  (images("u-logo"), photos)
+}
+
+fn representative_h_card<'a>(document: &'a Html, url: &Url) -> Option<ElementRef<'a>> {
+  let mut page = url.clone();
+  page.set_fragment(None);
+
+  let cards = document.select(selector!(".h-card")).collect::<Vec<_>>();
+
+  let urls = |card, name| {
+    let mut urls = properties(card, name)
+      .into_iter()
+      .filter_map(|property| url_property(property, url))
+      .collect::<Vec<_>>();
+
+    if name == "u-url" && urls.is_empty() && !has_explicit_properties(card, &["u-"]) {
+      urls.extend(implied_url(card, url));
+    }
+
+    urls
+      .into_iter()
+      .map(|mut url| {
+        url.set_fragment(None);
+        url
+      })
+      .collect::<Vec<_>>()
+  };
+
+  let rel_me = document
+    .select(selector!("a[rel~='me'][href]", "link[rel~='me'][href]"))
+    .filter_map(|elem_ref| url.join(elem_ref.value().attr("href")?.trim()).ok())
+    .collect::<Vec<_>>();
+
+  // an h-card with a uid and url of the page itself
+  cards
+    .iter()
+    .find(|card| urls(**card, "u-uid").contains(&page) && urls(**card, "u-url").contains(&page))
+    // an h-card with a url that's also linked with rel=me
+    .or_else(|| {
+      cards
+        .iter()
+        .find(|card| urls(**card, "u-url").iter().any(|url| rel_me.contains(url)))
+    })
+    // the only h-card on the page, with a url of the page itself
+    .or_else(|| match cards.as_slice() {
+      [card] if urls(*card, "u-url").contains(&page) => Some(card),
+      _ => None,
+    })
+    .copied()
+}
+
+fn has_class(elem_ref: ElementRef<'_>, predicate: impl Fn(&str) -> bool) -> bool {
+  elem_ref
+    .value()
+    .attr("class")
+    .map(|class| class.split_ascii_whitespace().any(predicate))
+    .unwrap_or(false)
+}
+
+/// The elements of a microformat with the property class `name`, leaving out
+/// the properties of nested microformats
+fn properties<'a>(root: ElementRef<'a>, name: &str) -> Vec<ElementRef<'a>> {
+  let mut found = Vec::new();
+
+  for child in root.children().filter_map(ElementRef::wrap) {
+    if has_class(child, |class| class == name) {
+      found.push(child);
+    }
+
+    if !has_class(child, |class| class.starts_with("h-")) {
+      found.extend(properties(child, name));
+    }
+  }
+
+  found
+}
+
+/// Whether the microformat has properties with one of the class prefixes, or
+/// nested microformats, in which case its properties aren't implied
+fn has_explicit_properties(root: ElementRef<'_>, prefixes: &[&str]) -> bool {
+  root.children().filter_map(ElementRef::wrap).any(|child| {
+    has_class(child, |class| {
+      class.starts_with("h-") || prefixes.iter().any(|prefix| class.starts_with(prefix))
+    }) || has_explicit_properties(child, prefixes)
+  })
+}
+
+/// Parses a `u-*` property
+fn url_property(elem_ref: ElementRef<'_>, url: &Url) -> Option<Url> {
+  let elem = elem_ref.value();
+
+  let value = match elem.name() {
+    "a" | "area" | "link" => elem.attr("href"),
+    "img" | "audio" | "video" | "source" | "iframe" => elem.attr("src"),
+    "object" => elem.attr("data"),
+    _ => None,
+  };
+
+  let value = match value {
+    Some(value) => value.to_string(),
+    None => elem_ref.text().collect::<String>(),
+  };
+
+  if value.trim().is_empty() {
+    None
+  } else {
+    url.join(value.trim()).ok()
+  }
+}
+
+/// The implied `url` of an `<a>` h-card, or of an h-card with a single `<a>`
+/// child (or grandchild, through an only child)
+fn implied_url(card: ElementRef<'_>, url: &Url) -> Option<Url> {
+  let link = implied_element(card, &["a", "area"])?;
+  url.join(link.value().attr("href")?.trim()).ok()
+}
+
+/// The implied `photo` of an `<img>` h-card, or of an h-card with a single
+/// `<img>` child (or grandchild, through an only child like `<a class="u-url"><img></a>`)
+fn implied_photo(card: ElementRef<'_>, url: &Url) -> Option<Url> {
+  let img = implied_element(card, &["img"])?;
+  url.join(img.value().attr("src")?.trim()).ok()
+}
+
+/// The microformat itself when it's one of the elements, otherwise its only
+/// child of these types, or that of its only child
+fn implied_element<'a>(card: ElementRef<'a>, names: &[&str]) -> Option<ElementRef<'a>> {
+  if names.contains(&card.value().name()) {
+    return Some(card);
+  }
+
+  only_of_type(card, names).or_else(|| {
+    match card
+      .children()
+      .filter_map(ElementRef::wrap)
+      .collect::<Vec<_>>()
+      .as_slice()
+    {
+      [child] if !has_class(*child, |class| class.starts_with("h-")) => only_of_type(*child, names),
+      _ => None,
+    }
+  })
+}
+
+fn only_of_type<'a>(elem_ref: ElementRef<'a>, names: &[&str]) -> Option<ElementRef<'a>> {
+  match elem_ref
+    .children()
+    .filter_map(ElementRef::wrap)
+    .filter(|child| names.contains(&child.value().name()))
+    .collect::<Vec<_>>()
+    .as_slice()
+  {
+    [elem] if !has_class(*elem, |class| class.starts_with("h-")) => Some(*elem),
+    _ => None,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn logos(html: &str) -> (Vec<String>, Vec<String>) {
+    let url = Url::parse("https://example.com/").unwrap();
+    let (logos, photos) = parse_h_card_logos(&Html::parse_document(html), &url);
+    let strings = |urls: Vec<Url>| urls.into_iter().map(String::from).collect();
+
+    (strings(logos), strings(photos))
+  }
+
+  #[test]
+  fn implies_url_and_photo() {
+    assert_eq!(
+      logos(r#"<a class="h-card" href="/"><img src="/alice.jpg">Alice</a>"#),
+      (vec![], vec!["https://example.com/alice.jpg".to_string()])
+    );
+    assert_eq!(
+      logos(r#"<div class="h-card"><a href="/"><img src="/alice.jpg"></a></div>"#),
+      (vec![], vec!["https://example.com/alice.jpg".to_string()])
+    );
+
+    // not the page's own card
+    assert_eq!(
+      logos(r#"<a class="h-card" href="https://bob.example/"><img src="/bob.jpg">Bob</a>"#),
+      (vec![], vec![])
+    );
+  }
+
+  #[test]
+  fn skips_implied_photo_with_explicit_properties() {
+    assert_eq!(
+      logos(
+        r#"<div class="h-card">
+          <a class="u-url u-uid" href="/"><img src="/avatar.jpg"></a>
+          <img class="u-logo" src="/logo.png">
+        </div>"#
+      ),
+      (vec!["https://example.com/logo.png".to_string()], vec![])
+    );
+    assert_eq!(
+      logos(
+        r#"<div class="h-card">
+          <a class="u-url u-uid" href="/"><img class="u-photo" src="/me.jpg"></a>
+          <div class="h-card"><img class="u-logo" src="/nested.png"></div>
+        </div>"#
+      ),
+      (vec![], vec!["https://example.com/me.jpg".to_string()])
+    );
+  }
+
+  #[test]
+  fn picks_representative_h_card() {
+    assert_eq!(
+      logos(
+        r#"<div class="h-card"><a class="u-url" href="https://alice.example/"></a><img class="u-logo" src="/alice.png"></div>
+        <div class="h-card"><a class="u-url" href="https://bob.example/"></a><img class="u-logo" src="/bob.png"></div>
+        <a rel="me" href="https://bob.example/">Me</a>"#
+      ),
+      (vec!["https://example.com/bob.png".to_string()], vec![])
+    );
+  }
+}
@@ -1,121 +1,310 @@
+use crate::feed::FeedKind;
 use crate::utils::poll_in_background;
+use crate::Diagnostic;
 use crate::Icon;
+use crate::IconCandidate;
 use crate::IconKind;
+use crate::IconSource;
+use crate::NativeApp;
 use crate::SiteIcons;
-use futures::future::join_all;
+use crate::SiteMetadata;
+use futures::future::{join_all, LocalBoxFuture, Shared};
+use futures::Future;
 use futures::FutureExt;
-use futures::Stream;
-use futures::StreamExt;
-use lol_html::{element, errors::RewritingError, HtmlRewriter, Settings};
-use std::{
-  cell::RefCell,
-  error::Error,
-  fmt::{self, Display},
-};
+use once_cell::sync::Lazy;
+use scraper::{ElementRef, Html, Selector};
 use url::Url;
 
-#[derive(Debug)]
-struct EndOfHead {}
+const LINK_SELECTOR: &str = join_with!(
+  ",",
+  "link[rel~='manifest']",
+  "link[rel~='icon']",
+  "link[rel~='apple-touch-icon']",
+  "link[rel~='apple-touch-icon-precomposed']",
+  "link[rel~='apple-touch-startup-image']",
+  "link[rel~='fluid-icon']",
+  "link[rel~='alternate'][type]",
+  "link[rel~='search']"
+);
 
-impl Display for EndOfHead {
-  fn fmt(&self, _: &mut fmt::Formatter<'_>) -> fmt::Result {
-    Ok(())
+const FEED_TYPES: &[&str] = &["application/rss+xml", "application/atom+xml"];
+const OPENSEARCH_TYPE: &str = "application/opensearchdescription+xml";
+
+static LINK_ELEMENTS: Lazy<Selector> = Lazy::new(|| Selector::parse(LINK_SELECTOR).unwrap());
+
+#[derive(Debug, Clone, Default)]
+pub struct HeadTags {
+  pub icons: Vec<Icon>,
+  /// Metadata from the manifests linked in the head
+  pub metadata: SiteMetadata,
+  /// Apps from smart app banner `<meta>` tags and the linked manifests
+  pub apps: Vec<NativeApp>,
+  pub diagnostics: Vec<Diagnostic>,
+}
+
+impl HeadTags {
+  fn extend(&mut self, other: HeadTags) {
+    self.icons.extend(other.icons);
+    self.apps.extend(other.apps);
+    self.metadata.merge(&other.metadata);
+    self.diagnostics.extend(other.diagnostics);
   }
 }
 
-impl Error for EndOfHead {}
+/// A `<link>` tag, or the equivalent `Link` response header
+#[derive(Debug, Clone)]
+pub struct Link {
+  pub rel: String,
+  pub href: String,
+  pub sizes: Option<String>,
+  pub mime_type: Option<String>,
+  pub media: Option<String>,
+  pub source: IconSource,
+}
+
+type LoadingLink = Shared<LocalBoxFuture<'static, HeadTags>>;
+
+impl Link {
+  fn from_element_ref(elem_ref: ElementRef<'_>, source: IconSource) -> Option<Link> {
+    let elem = elem_ref.value();
+
+    Some(Link {
+      rel: elem.attr("rel")?.to_string(),
+      href: elem.attr("href")?.to_string(),
+      sizes: elem.attr("sizes").map(String::from),
+      mime_type: elem.attr("type").map(String::from),
+      media: elem.attr("media").map(String::from),
+      source,
+    })
+  }
+
+  fn has_rel(&self, rels: &[&str]) -> bool {
+    self
+      .rel
+      .split_ascii_whitespace()
+      .any(|rel| rels.iter().any(|other| rel.eq_ignore_ascii_case(other)))
+  }
+
+  /// The manifest URL, for `rel=manifest` links
+  pub fn manifest_url(&self, url: &Url) -> Option<Url> {
+    if self.has_rel(&["manifest"]) {
+      url.join(self.href.trim()).ok()
+    } else {
+      None
+    }
+  }
+
+  /// The feed or OpenSearch description URL, for `rel=alternate` RSS/Atom
+  /// links and `rel=search` links
+  pub fn feed_url(&self, url: &Url) -> Option<(Url, FeedKind)> {
+    let mime_type = self
+      .mime_type
+      .as_deref()
+      .and_then(|mime_type| mime_type.split(';').next())
+      .unwrap_or_default()
+      .trim()
+      .to_ascii_lowercase();
+
+    let kind = if self.has_rel(&["alternate"]) && FEED_TYPES.contains(&mime_type.as_str()) {
+      FeedKind::Feed
+    } else if self.has_rel(&["search"]) && (mime_type.is_empty() || mime_type == OPENSEARCH_TYPE) {
+      FeedKind::OpenSearch
+    } else {
+      return None;
+    };
+
+    Some((url.join(self.href.trim()).ok()?, kind))
+  }
+
+  /// The icon declared by the link, if it's an icon link
+  pub fn icon_candidate(&self, url: &Url) -> Option<IconCandidate> {
+    if self.has_rel(&["manifest"]) {
+      return None;
+    }
+
+    let kind = if self.has_rel(&["apple-touch-startup-image"]) {
+      IconKind::AppStartupImage
+    } else if self.has_rel(&[
+      "apple-touch-icon",
+      "apple-touch-icon-precomposed",
+      // the legacy Fluid.app icon, usually a large square
+      "fluid-icon",
+    ]) {
+      IconKind::AppIcon
+    } else if self.has_rel(&["icon"]) {
+      IconKind::SiteFavicon
+    } else {
+      return None;
+    };
+
+    Some(IconCandidate {
+      href: url.join(self.href.trim()).ok()?,
+      kind,
+      rel: Some(self.rel.clone()),
+      sizes: self.sizes.clone(),
+      mime_type: self.mime_type.clone(),
+      media: self.media.clone(),
+      purpose: Vec::new(),
+      source: self.source.clone(),
+    })
+  }
+
+  /// Starts loading the icons referenced by the link
+  fn load(self, url: &Url) -> Option<LoadingLink> {
+    if let Some(href) = self.manifest_url(url) {
+      return Some(
+        async {
+          SiteIcons::load_manifest_with_metadata(href)
+            .await
+            .map(|manifest| HeadTags {
+              icons: manifest.icons,
+              metadata: manifest.metadata,
+              apps: manifest.apps,
+              diagnostics: manifest.diagnostics,
+            })
+            .unwrap_or_default()
+        }
+        .boxed_local()
+        .shared(),
+      );
+    }
+
+    if let Some((href, kind)) = self.feed_url(url) {
+      return Some(
+        async move {
+          let candidates = SiteIcons::discover_feed(href, kind)
+            .await
+            .unwrap_or_default();
 
+          HeadTags {
+            icons: join_all(candidates.into_iter().map(IconCandidate::resolve))
+              .await
+              .into_iter()
+              .flatten()
+              .collect(),
+            ..Default::default()
+          }
+        }
+        .boxed_local()
+        .shared(),
+      );
+    }
+
+    let candidate = self.icon_candidate(url)?;
+
+    Some(
+      async {
+        let icons = candidate
+          .resolve()
+          .await
+          .map(|icon| vec![icon])
+          .unwrap_or_default();
+
+        HeadTags {
+          icons,
+          ..Default::default()
+        }
+      }
+      .boxed_local()
+      .shared(),
+    )
+  }
+}
+
+/// The `<link>` tags and smart app banners of a page, sent by [`parse_page`](super::parse_page)
+/// once its head has been parsed
+#[derive(Debug, Clone, Default)]
+pub struct PageHead {
+  pub links: Vec<Link>,
+  pub apps: Vec<NativeApp>,
+}
+
+/// Loads the icons from the `Link` headers of the page response, along with
+/// the `<link>` tags in the head once it has been parsed. The icons of the
+/// headers start loading while the page is still being parsed.
+///
+/// Only the first RSS/Atom feed and OpenSearch description are followed,
+/// the others are usually comment or category feeds. Startup images are
+/// skipped, unless `startup_images` is set.
 pub async fn parse_head(
   url: &Url,
-  mut body: impl Stream<Item = Result<Vec<u8>, String>> + Unpin,
-) -> Result<Vec<Icon>, Box<dyn Error>> {
-  let mut icons = Vec::new();
-  let new_icons = RefCell::new(Vec::new());
-
-  {
-    let mut rewriter = HtmlRewriter::new(
-      Settings {
-        element_content_handlers: vec![
-          element!("head", |head| {
-            head.on_end_tag(|_| Err(Box::new(EndOfHead {})))?;
-            Ok(())
-          }),
-          element!("link[rel~='manifest']", |manifest| {
-            if let Some(href) = manifest
-              .get_attribute("href")
-              .and_then(|href| url.join(&href).ok())
-            {
-              new_icons.borrow_mut().push(
-                async { SiteIcons::load_manifest(href).await.unwrap_or(Vec::new()) }
-                  .boxed_local()
-                  .shared(),
-              )
-            }
-
-            Ok(())
-          }),
-          element!(
-            join_with!(
-              ",",
-              "link[rel~='icon']",
-              "link[rel~='apple-touch-icon']",
-              "link[rel~='apple-touch-icon-precomposed']"
-            ),
-            |link| {
-              let rel = link.get_attribute("rel").unwrap();
-
-              if let Some(href) = link
-                .get_attribute("href")
-                .and_then(|href| url.join(&href).ok())
-              {
-                let kind = if rel.contains("apple-touch-icon") {
-                  IconKind::AppIcon
-                } else {
-                  IconKind::SiteFavicon
-                };
-
-                let sizes = link.get_attribute("sizes");
-
-                new_icons.borrow_mut().push(
-                  async {
-                    Icon::load(href, kind, sizes)
-                      .await
-                      .map(|icon| vec![icon])
-                      .unwrap_or(Vec::new())
-                  }
-                  .boxed_local()
-                  .shared(),
-                )
-              };
-
-              Ok(())
-            }
-          ),
-        ],
-        ..Settings::default()
-      },
-      |_: &[u8]| {},
-    );
-
-    while let Some(data) = poll_in_background(body.next(), join_all(icons.clone())).await {
-      let result = rewriter.write(&data?);
-
-      icons.extend(new_icons.borrow_mut().drain(..));
-
-      match result {
-        Err(RewritingError::ContentHandlerError(result)) => {
-          match result.downcast::<EndOfHead>() {
-            Ok(_) => break,
-            Err(err) => return Err(err),
-          };
-        }
+  links: Vec<Link>,
+  head: impl Future<Output = Option<PageHead>> + Unpin,
+  startup_images: bool,
+) -> HeadTags {
+  let mut followed_feeds = Vec::new();
+  let mut load_link = |link: Link| {
+    if !startup_images && link.has_rel(&["apple-touch-startup-image"]) {
+      return None;
+    }
 
-        result => result?,
+    if let Some((_, kind)) = link.feed_url(url) {
+      if followed_feeds.contains(&kind) {
+        return None;
       }
+
+      followed_feeds.push(kind);
     }
+
+    link.load(url)
+  };
+
+  let mut icons = links
+    .into_iter()
+    .filter_map(&mut load_link)
+    .collect::<Vec<_>>();
+
+  let head = poll_in_background(head, join_all(icons.clone()))
+    .await
+    .unwrap_or_default();
+  icons.extend(head.links.into_iter().filter_map(load_link));
+
+  let mut head_tags = HeadTags {
+    apps: head.apps,
+    ..Default::default()
+  };
+
+  for loaded in join_all(icons).await {
+    head_tags.extend(loaded);
   }
 
-  let icons = join_all(icons).await.into_iter().flatten().collect();
+  head_tags
+}
+
+/// Finds the `<link>` tags of a parsed document. Links that the parser
+/// placed outside of the head are marked as [`IconSource::BodyTag`].
+pub fn parse_head_links(document: &Html) -> Vec<Link> {
+  parse_links(document, |elem_ref| {
+    elem_ref
+      .ancestors()
+      .filter_map(ElementRef::wrap)
+      .any(|ancestor| ancestor.value().name() == "head")
+  })
+}
+
+/// The `<link>` tags of the page, with `in_head` telling which ones are `HeadTag`s
+pub fn parse_links(document: &Html, in_head: impl Fn(ElementRef) -> bool) -> Vec<Link> {
+  document
+    .select(&LINK_ELEMENTS)
+    .filter_map(|elem_ref| {
+      let source = if in_head(elem_ref) {
+        IconSource::HeadTag
+      } else {
+        IconSource::BodyTag
+      };
+
+      Link::from_element_ref(elem_ref, source)
+    })
+    .collect()
+}
 
-  Ok(icons)
+/// The apps declared by smart app banner `<meta>` tags
+pub fn parse_native_apps(document: &Html) -> Vec<NativeApp> {
+  document
+    .select(selector!("meta[name][content]"))
+    .filter_map(|meta| {
+      let meta = meta.value();
+      NativeApp::from_meta(meta.attr("name")?, meta.attr("content")?)
+    })
+    .collect()
 }
@@ -0,0 +1,266 @@
+use super::StyleRule;
+use itertools::Itertools;
+use scraper::{ElementRef, Html, Node};
+use std::collections::HashSet;
+
+/// Serializes an inline `<svg>` so that it renders the same outside of the
+/// page, e.g. as a data URI:
+/// - `<symbol>`s, gradients etc. it references from elsewhere in the document
+///   are copied into a `<defs>`
+/// - the page's style rules that apply to its elements are inlined
+/// - `currentColor` is replaced with the `color` it inherits, when it's known
+pub fn standalone_svg(svg: ElementRef, document: &Html, style_rules: &[&StyleRule]) -> String {
+  let color = svg
+    .ancestors()
+    .filter_map(ElementRef::wrap)
+    .collect::<Vec<_>>()
+    .into_iter()
+    .rev()
+    .fold(None, |inherited, elem_ref| {
+      declared_color(elem_ref, style_rules).or(inherited)
+    });
+
+  let mut defined = svg
+    .descendants()
+    .filter_map(|node| node.value().as_element()?.id())
+    .map(|id| id.to_string())
+    .collect::<HashSet<_>>();
+
+  let mut markup = String::new();
+  write_element(svg, color.as_deref(), style_rules, "", &mut markup);
+
+  // references can be nested, e.g. a <symbol> using a gradient
+  let mut defs = String::new();
+  let mut unresolved = references(&markup);
+  while let Some(id) = unresolved.pop() {
+    if !defined.insert(id.clone()) {
+      continue;
+    }
+
+    let referenced = document
+      .root_element()
+      .descendants()
+      .filter_map(ElementRef::wrap)
+      .find(|elem_ref| elem_ref.value().id() == Some(id.as_str()));
+
+    if let Some(referenced) = referenced {
+      let mut def = String::new();
+      write_element(referenced, color.as_deref(), style_rules, "", &mut def);
+      unresolved.extend(references(&def));
+      defs.push_str(&def);
+    }
+  }
+
+  if defs.is_empty() {
+    return markup;
+  }
+
+  let mut markup = String::new();
+  write_element(
+    svg,
+    color.as_deref(),
+    style_rules,
+    &format!("<defs>{}</defs>", defs),
+    &mut markup,
+  );
+  markup
+}
+
+/// The ids referenced through `href="#id"` or `url(#id)`
+fn references(markup: &str) -> Vec<String> {
+  regex!(r##"href="#([^"]+)"|url\(\s*(?:&#39;|&quot;)?#([^)&]+)"##)
+    .captures_iter(markup)
+    .filter_map(|captures| {
+      Some(
+        captures
+          .get(1)
+          .or_else(|| captures.get(2))?
+          .as_str()
+          .to_string(),
+      )
+    })
+    .collect()
+}
+
+/// The declarations of the style rules matching the element, followed by its
+/// inline style
+fn computed_style(elem_ref: ElementRef, style_rules: &[&StyleRule]) -> String {
+  style_rules
+    .iter()
+    .filter(|rule| !rule.pseudo_element && rule.selector.matches(&elem_ref))
+    .map(|rule| rule.declarations.as_str())
+    .chain(elem_ref.value().attr("style"))
+    .map(|declarations| declarations.trim().trim_end_matches(';'))
+    .filter(|declarations| !declarations.is_empty())
+    .collect::<Vec<_>>()
+    .join(";")
+}
+
+fn declared_color(elem_ref: ElementRef, style_rules: &[&StyleRule]) -> Option<String> {
+  let style = computed_style(elem_ref, style_rules);
+
+  regex!(r"(?i)(?:^|;)\s*color\s*:([^;]*)")
+    .captures_iter(&style)
+    .map(|captures| captures[1].replace("!important", "").trim().to_string())
+    .last()
+    .or_else(|| {
+      elem_ref
+        .value()
+        .attr("color")
+        .map(|color| color.trim().to_string())
+    })
+    .filter(|color| {
+      !color.is_empty()
+        && !["inherit", "currentcolor", "initial", "unset", "revert"]
+          .contains(&color.to_ascii_lowercase().as_str())
+    })
+}
+
+fn escape(text: &str) -> String {
+  text
+    .replace('&', "&amp;")
+    .replace('<', "&lt;")
+    .replace('>', "&gt;")
+    .replace('"', "&quot;")
+    .replace('\'', "&#39;")
+}
+
+fn write_element(
+  elem_ref: ElementRef,
+  inherited_color: Option<&str>,
+  style_rules: &[&StyleRule],
+  prepend: &str,
+  out: &mut String,
+) {
+  let elem = elem_ref.value();
+  let style = computed_style(elem_ref, style_rules);
+  let color = declared_color(elem_ref, style_rules);
+  let color = color.as_deref().or(inherited_color);
+
+  let resolve = |value: &str| match color {
+    Some(color) => regex!(r"(?i)currentcolor")
+      .replace_all(value, color)
+      .to_string(),
+    None => value.to_string(),
+  };
+
+  out.push('<');
+  out.push_str(elem.name());
+  // `xlink:href` becomes `href`, as the xlink namespace isn't declared.
+  // Sorted, as scraper doesn't keep the attribute order.
+  let attrs = elem
+    .attrs
+    .iter()
+    .filter(|(name, _)| name.prefix.as_deref() != Some("xmlns") && &*name.local != "xmlns")
+    .map(|(name, value)| (&*name.local, &**value))
+    .filter(|(name, _)| *name != "style")
+    .sorted()
+    .dedup_by(|(a, _), (b, _)| a == b);
+
+  for (name, value) in attrs {
+    out.push_str(&format!(" {}=\"{}\"", name, escape(&resolve(value))));
+  }
+  if !style.is_empty() {
+    out.push_str(&format!(" style=\"{}\"", escape(&resolve(&style))));
+  }
+
+  let mut children = String::from(prepend);
+  for child in elem_ref.children() {
+    match child.value() {
+      Node::Element(_) => {
+        let child = ElementRef::wrap(child).unwrap();
+        // the data URI is an image, scripts can't run anyway
+        if child.value().name() != "script" {
+          write_element(child, color, style_rules, "", &mut children);
+        }
+      }
+      Node::Text(text) => children.push_str(&escape(text)),
+      _ => {}
+    }
+  }
+
+  if children.is_empty() {
+    out.push_str("/>");
+  } else {
+    out.push('>');
+    out.push_str(&children);
+    out.push_str(&format!("</{}>", elem.name()));
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::super::parse_style_rules;
+  use super::*;
+  use url::Url;
+
+  fn standalone(html: &str, css: &str) -> String {
+    let url = Url::parse("https://example.com/").unwrap();
+    let document = Html::parse_document(html);
+    let rules = parse_style_rules(css, &url);
+    let rules = rules.iter().collect::<Vec<_>>();
+    let svg = document.select(selector!("#logo")).next().unwrap();
+
+    standalone_svg(svg, &document, &rules)
+  }
+
+  #[test]
+  fn copies_sprite_symbols() {
+    assert_eq!(
+      standalone(
+        r##"<svg style="display: none"><symbol id="icon" viewBox="0 0 10 10"><path d="M0 0h10v10z"/></symbol></svg>
+        <a href="/"><svg id="logo"><use xlink:href="#icon"></use></svg></a>"##,
+        "",
+      ),
+      r##"<svg id="logo"><defs><symbol id="icon" viewBox="0 0 10 10"><path d="M0 0h10v10z"/></symbol></defs><use href="#icon"/></svg>"##
+    );
+  }
+
+  #[test]
+  fn copies_nested_references() {
+    let svg = standalone(
+      r##"<svg><defs>
+        <linearGradient id="base"><stop offset="0" stop-color="red"/></linearGradient>
+        <linearGradient id="fade" href="#base"/>
+        <symbol id="mark"><rect fill="url(#fade)" width="1" height="1"/></symbol>
+      </defs></svg>
+      <svg id="logo"><use href="#mark"/><circle id="own" r="1"/><use href="#own"/></svg>"##,
+      "",
+    );
+
+    assert!(svg.contains(r#"<symbol id="mark">"#));
+    assert!(svg.contains(r##"<linearGradient href="#base" id="fade"/>"##));
+    assert!(svg.contains(r#"<linearGradient id="base">"#));
+    // ids defined inside the <svg> itself aren't copied
+    assert_eq!(svg.matches(r#"id="own""#).count(), 1);
+  }
+
+  #[test]
+  fn resolves_current_color() {
+    assert_eq!(
+      standalone(
+        r#"<header style="color: #f00"><a class="brand"><svg id="logo"><path fill="currentColor" style="stroke: currentcolor"/></svg></a></header>"#,
+        ".brand { color: blue !important }",
+      ),
+      r#"<svg id="logo"><path fill="blue" style="stroke: blue"/></svg>"#
+    );
+    assert_eq!(
+      standalone(
+        r#"<div color="inherit"><svg id="logo"><path fill="currentColor"/></svg></div>"#,
+        "",
+      ),
+      r#"<svg id="logo"><path fill="currentColor"/></svg>"#
+    );
+  }
+
+  #[test]
+  fn inlines_matching_style_rules() {
+    assert_eq!(
+      standalone(
+        r#"<svg id="logo"><path class="a"/><script>alert(1)</script></svg>"#,
+        "#logo .a { fill: red; } .a::before { content: 'x' }",
+      ),
+      r#"<svg id="logo"><path class="a" style="fill: red"/></svg>"#
+    );
+  }
+}
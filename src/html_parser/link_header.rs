@@ -0,0 +1,218 @@
+use super::Link;
+use crate::IconSource;
+use reqwest::header::{HeaderMap, LINK};
+use std::{iter::Peekable, str::Chars};
+
+/// Parses the [RFC 8288](https://www.rfc-editor.org/rfc/rfc8288) `Link`
+/// headers of a response, e.g. `</static/icon.svg>; rel="icon"`
+pub fn parse_link_headers(headers: &HeaderMap) -> Vec<Link> {
+  headers
+    .get_all(LINK)
+    .iter()
+    .filter_map(|value| value.to_str().ok())
+    .flat_map(parse_link_header)
+    .collect()
+}
+
+fn parse_link_header(value: &str) -> Vec<Link> {
+  let mut links = Vec::new();
+  let mut chars = value.chars().peekable();
+
+  loop {
+    skip_while(&mut chars, |c| c.is_whitespace() || c == ',');
+
+    match chars.next() {
+      Some('<') => {}
+      Some(_) => {
+        skip_while(&mut chars, |c| c != ',');
+        continue;
+      }
+      None => break,
+    }
+
+    let href = chars.by_ref().take_while(|c| *c != '>').collect::<String>();
+
+    let mut rel = None;
+    let mut sizes = None;
+    let mut mime_type = None;
+    let mut media = None;
+    let mut anchor = None;
+
+    // link-params, up until the next link
+    loop {
+      skip_while(&mut chars, char::is_whitespace);
+
+      match chars.next() {
+        Some(';') => {}
+        Some(',') | None => break,
+        // garbage, skip to the next link
+        Some(_) => {
+          skip_while(&mut chars, |c| c != ',');
+          continue;
+        }
+      }
+
+      skip_while(&mut chars, char::is_whitespace);
+      let name = take_while(&mut chars, |c| {
+        !matches!(c, '=' | ';' | ',') && !c.is_whitespace()
+      })
+      .to_ascii_lowercase();
+      skip_while(&mut chars, char::is_whitespace);
+
+      let value = if chars.peek() == Some(&'=') {
+        chars.next();
+        skip_while(&mut chars, char::is_whitespace);
+        parse_param_value(&mut chars)
+      } else {
+        String::new()
+      };
+
+      // the first occurrence of a parameter wins
+      match name.as_str() {
+        "rel" if rel.is_none() => rel = Some(value),
+        "sizes" if sizes.is_none() => sizes = Some(value),
+        "type" if mime_type.is_none() => mime_type = Some(value),
+        "media" if media.is_none() => media = Some(value),
+        "anchor" if anchor.is_none() => anchor = Some(value),
+        _ => {}
+      }
+    }
+
+    // links with an anchor describe some other resource
+    if let (Some(rel), None) = (rel, anchor) {
+      links.push(Link {
+        rel,
+        href,
+        sizes,
+        mime_type,
+        media,
+        source: IconSource::LinkHeader,
+      });
+    }
+  }
+
+  links
+}
+
+fn parse_param_value(chars: &mut Peekable<Chars>) -> String {
+  if chars.peek() != Some(&'"') {
+    return take_while(chars, |c| !matches!(c, ';' | ',') && !c.is_whitespace());
+  }
+
+  chars.next();
+
+  let mut value = String::new();
+  while let Some(c) = chars.next() {
+    match c {
+      '"' => break,
+      '\\' => value.extend(chars.next()),
+      c => value.push(c),
+    }
+  }
+
+  value
+}
+
+fn take_while(chars: &mut Peekable<Chars>, predicate: impl Fn(char) -> bool) -> String {
+  let mut value = String::new();
+  while let Some(c) = chars.next_if(|c| predicate(*c)) {
+    value.push(c);
+  }
+  value
+}
+
+fn skip_while(chars: &mut Peekable<Chars>, predicate: impl Fn(char) -> bool) {
+  while chars.next_if(|c| predicate(*c)).is_some() {}
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use reqwest::header::HeaderValue;
+
+  fn rels(value: &str) -> Vec<(String, String)> {
+    parse_link_header(value)
+      .into_iter()
+      .map(|link| (link.rel, link.href))
+      .collect()
+  }
+
+  fn rel(rel: &str, href: &str) -> (String, String) {
+    (rel.to_string(), href.to_string())
+  }
+
+  #[test]
+  fn parses_params() {
+    let links = parse_link_header(
+      r#"</icon.svg>; REL="icon" ; sizes=any; type="image/svg+xml"; media="(prefers-color-scheme: dark)"; rel=preload"#,
+    );
+
+    assert_eq!(links.len(), 1);
+    assert_eq!(links[0].rel, "icon");
+    assert_eq!(links[0].href, "/icon.svg");
+    assert_eq!(links[0].sizes.as_deref(), Some("any"));
+    assert_eq!(links[0].mime_type.as_deref(), Some("image/svg+xml"));
+    assert_eq!(
+      links[0].media.as_deref(),
+      Some("(prefers-color-scheme: dark)")
+    );
+    assert_eq!(links[0].source, IconSource::LinkHeader);
+  }
+
+  #[test]
+  fn unescapes_quoted_values() {
+    let links = parse_link_header(r#"</a.png>; rel="icon"; media="a \"quoted\, value\\""#);
+
+    assert_eq!(links.len(), 1);
+    assert_eq!(links[0].media.as_deref(), Some(r#"a "quoted, value\"#));
+  }
+
+  #[test]
+  fn parses_multiple_links() {
+    assert_eq!(
+      rels(
+        r#"</a.png>; rel=icon, <https://example.com/m.json>; rel="manifest",</b.png>;rel="apple-touch-icon""#
+      ),
+      vec![
+        rel("icon", "/a.png"),
+        rel("manifest", "https://example.com/m.json"),
+        rel("apple-touch-icon", "/b.png"),
+      ]
+    );
+  }
+
+  #[test]
+  fn skips_anchored_links() {
+    assert_eq!(
+      rels(r#"</other.png>; rel=icon; anchor="/other", </a.png>; rel=icon"#),
+      vec![rel("icon", "/a.png")]
+    );
+  }
+
+  #[test]
+  fn recovers_from_garbage() {
+    assert_eq!(
+      rels("garbage, </a.png>; rel=icon junk, </b.png>, </c.png>; rel=icon"),
+      vec![rel("icon", "/a.png"), rel("icon", "/c.png")]
+    );
+    assert_eq!(rels(r#"</a.png>; rel="icon"#), vec![rel("icon", "/a.png")]);
+    assert_eq!(rels("</a.png"), vec![]);
+    assert_eq!(rels(""), vec![]);
+  }
+
+  #[test]
+  fn parses_every_header() {
+    let mut headers = HeaderMap::new();
+    headers.append(LINK, HeaderValue::from_static("</a.png>; rel=icon"));
+    headers.append(LINK, HeaderValue::from_static("</m.json>; rel=manifest"));
+
+    let links = parse_link_headers(&headers);
+    assert_eq!(
+      links
+        .into_iter()
+        .map(|link| (link.rel, link.href))
+        .collect::<Vec<_>>(),
+      vec![rel("icon", "/a.png"), rel("manifest", "/m.json")]
+    );
+  }
+}
@@ -1,5 +1,23 @@
+mod css;
+mod embed;
+mod h_card;
 mod head;
+mod inline_svg;
+mod link_header;
+mod page;
+mod redirect;
+mod schema_org;
 mod site_logo;
+mod srcset;
 
+pub use css::*;
+pub use embed::*;
+pub use h_card::*;
 pub use head::*;
+pub use inline_svg::*;
+pub use link_header::*;
+pub use page::*;
+pub use redirect::*;
+pub use schema_org::*;
 pub use site_logo::*;
+pub use srcset::*;
@@ -0,0 +1,377 @@
+use super::{
+  has_decisive_logo, load_style_rules, parse_links, parse_native_apps, site_logo_candidates,
+  stylesheet_urls, PageHead,
+};
+use crate::{Icon, IconSource, LogoHeuristics};
+use futures::{channel::oneshot, Stream, StreamExt};
+use html5ever::{
+  tendril::{stream::Utf8LossyDecoder, ByteTendril, StrTendril, TendrilSink},
+  tokenizer::{
+    BufferQueue, Tag, TagKind, Token, TokenSink, TokenSinkResult, Tokenizer, TokenizerResult,
+  },
+  tree_builder::{TreeBuilder, TreeSink},
+};
+use scraper::{ElementRef, Html};
+use std::{borrow::Cow, collections::HashSet, error::Error};
+use url::Url;
+
+/// The page parsed so far is scored once this many bytes have been read, and
+/// again each time the page size doubles, so large pages aren't rescored over and over
+const FIRST_CHECK_BYTES: usize = 16 * 1024;
+
+type NodeId = <Html as TreeSink>::Handle;
+
+/// Passes the tokens on to the tree builder, and notes the `<link>`s parsed
+/// before the head is closed by a `</head>` or `<body>` tag. The tree builder
+/// also closes the head at the first stray element, which the page's author
+/// didn't intend.
+struct HeadWatcher {
+  tree_builder: TreeBuilder<NodeId, Html>,
+  head_links: Option<HashSet<NodeId>>,
+}
+
+impl TokenSink for HeadWatcher {
+  type Handle = NodeId;
+
+  fn process_token(&mut self, token: Token, line_number: u64) -> TokenSinkResult<NodeId> {
+    if let Token::TagToken(Tag { kind, name, .. }) = &token {
+      let closes_head = match kind {
+        TagKind::StartTag => &**name == "body",
+        TagKind::EndTag => &**name == "head",
+      };
+
+      if closes_head && self.head_links.is_none() {
+        self.head_links = Some(
+          self
+            .tree_builder
+            .sink
+            .select(selector!("link"))
+            .map(|link| link.id())
+            .collect(),
+        );
+      }
+    }
+
+    self.tree_builder.process_token(token, line_number)
+  }
+
+  fn end(&mut self) {
+    self.tree_builder.end()
+  }
+
+  fn adjusted_current_node_present_but_not_in_html_namespace(&self) -> bool {
+    self
+      .tree_builder
+      .adjusted_current_node_present_but_not_in_html_namespace()
+  }
+}
+
+/// Like html5ever's `driver::Parser`, with the [`HeadWatcher`] between the
+/// tokenizer and the tree builder
+struct PageParser {
+  tokenizer: Tokenizer<HeadWatcher>,
+  input: BufferQueue,
+}
+
+impl PageParser {
+  fn new() -> Self {
+    PageParser {
+      tokenizer: Tokenizer::new(
+        HeadWatcher {
+          tree_builder: TreeBuilder::new(Html::new_document(), Default::default()),
+          head_links: None,
+        },
+        Default::default(),
+      ),
+      input: BufferQueue::new(),
+    }
+  }
+
+  /// The document parsed so far
+  fn document(&self) -> &Html {
+    &self.tokenizer.sink.tree_builder.sink
+  }
+
+  /// The `<link>`s parsed before the head was closed, once it is
+  fn head_links(&self) -> Option<&HashSet<NodeId>> {
+    self.tokenizer.sink.head_links.as_ref()
+  }
+
+  /// Whether the element is a head tag, see [`in_head`]
+  fn in_head(&self, elem_ref: ElementRef) -> bool {
+    in_head(elem_ref)
+      || self
+        .head_links()
+        .is_some_and(|ids| ids.contains(&elem_ref.id()))
+  }
+}
+
+impl TendrilSink<html5ever::tendril::fmt::UTF8> for PageParser {
+  type Output = Html;
+
+  fn process(&mut self, tendril: StrTendril) {
+    self.input.push_back(tendril);
+    while let TokenizerResult::Script(_) = self.tokenizer.feed(&mut self.input) {}
+  }
+
+  fn error(&mut self, desc: Cow<'static, str>) {
+    self.tokenizer.sink.tree_builder.sink.parse_error(desc)
+  }
+
+  fn finish(mut self) -> Html {
+    while let TokenizerResult::Script(_) = self.tokenizer.feed(&mut self.input) {}
+    self.tokenizer.end();
+    self.tokenizer.sink.tree_builder.sink
+  }
+}
+
+/// Parses the page once as it streams in, for both its head and its site logo.
+///
+/// The links and apps of the head are sent to `head` once a `</head>` or
+/// `<body>` tag is reached, or when `body_scan_bytes` is non-zero, after that
+/// many bytes of the body have been scanned for misplaced `<link>` tags. Links
+/// before that tag are head tags, even when a stray element in the head made
+/// the parser move them into the body. The site logo is scored as the
+/// page is parsed, until a decisive logo is found or the byte budget of the
+/// heuristics is spent. The rest of the page isn't downloaded once both are done.
+pub async fn parse_page(
+  url: &Url,
+  mut body: impl Stream<Item = Result<Vec<u8>, String>> + Unpin,
+  head: oneshot::Sender<PageHead>,
+  body_scan_bytes: usize,
+  heuristics: &LogoHeuristics,
+  stylesheet_bytes: usize,
+  is_blacklisted: impl Fn(&Url) -> bool,
+) -> Result<Icon, Box<dyn Error>> {
+  let mut parser = Utf8LossyDecoder::new(PageParser::new());
+  let mut head = Some(head);
+  let mut head_end = None;
+  let mut found_logo = false;
+  let mut read_bytes = 0;
+  let mut next_check = FIRST_CHECK_BYTES;
+
+  while let Some(data) = body.next().await {
+    let data = data?;
+    parser.process(ByteTendril::from_slice(&data));
+    read_bytes += data.len();
+
+    let page = &parser.inner_sink;
+    let document = page.document();
+
+    if head.is_some() {
+      // without the tags, scanning the body starts with the first body element
+      if head_end.is_none()
+        && (page.head_links().is_some()
+          || (body_scan_bytes > 0 && document.select(selector!("body")).next().is_some()))
+      {
+        head_end = Some(read_bytes);
+      }
+
+      if head_end.is_some_and(|head_end| read_bytes - head_end >= body_scan_bytes) {
+        send_head(
+          &mut head,
+          document,
+          |elem_ref| page.in_head(elem_ref),
+          body_scan_bytes,
+        );
+      }
+    }
+
+    if !found_logo {
+      if heuristics
+        .max_bytes
+        .is_some_and(|max_bytes| read_bytes >= max_bytes)
+      {
+        found_logo = true;
+      } else if read_bytes >= next_check {
+        next_check = read_bytes * 2;
+
+        if has_decisive_logo(document, url, heuristics, &is_blacklisted) {
+          info!("{}: decisive logo after {} bytes", url, read_bytes);
+          found_logo = true;
+        }
+      }
+    }
+
+    // the head is parsed to the end, when the page doesn't close it
+    let head_done =
+      head.is_none() || (head_end.is_none() && document.select(selector!("body")).next().is_some());
+    if head_done && found_logo {
+      break;
+    }
+  }
+  drop(body);
+
+  let page = parser.inner_sink;
+  let head_links = page.head_links().cloned();
+  let document = page.finish();
+  send_head(
+    &mut head,
+    &document,
+    |elem_ref| {
+      in_head(elem_ref)
+        || head_links
+          .as_ref()
+          .is_some_and(|ids| ids.contains(&elem_ref.id()))
+    },
+    body_scan_bytes,
+  );
+
+  let urls = stylesheet_urls(&document, url)
+    .into_iter()
+    .filter(|url| !is_blacklisted(url))
+    .collect();
+  let stylesheets = load_style_rules(urls, stylesheet_bytes).await;
+
+  match site_logo_candidates(&document, url, heuristics, &stylesheets, is_blacklisted)
+    .into_iter()
+    .next()
+  {
+    Some(candidate) => candidate.resolve().await,
+    None => Err("No site logo found".into()),
+  }
+}
+
+/// Whether the element is inside the `<head>` of the parsed document
+fn in_head(elem_ref: ElementRef) -> bool {
+  elem_ref
+    .ancestors()
+    .filter_map(ElementRef::wrap)
+    .any(|ancestor| ancestor.value().name() == "head")
+}
+
+fn send_head(
+  head: &mut Option<oneshot::Sender<PageHead>>,
+  document: &Html,
+  in_head: impl Fn(ElementRef) -> bool,
+  body_scan_bytes: usize,
+) {
+  if let Some(head) = head.take() {
+    let links = parse_links(document, in_head)
+      .into_iter()
+      .filter(|link| body_scan_bytes > 0 || link.source == IconSource::HeadTag)
+      .collect();
+
+    // the receiver is gone when the head tags aren't needed anymore
+    let _ = head.send(PageHead {
+      links,
+      apps: parse_native_apps(document),
+    });
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use futures::stream;
+
+  fn head_links(html: &str) -> Option<Vec<String>> {
+    let mut parser = PageParser::new();
+    parser.process(html.into());
+
+    let document = parser.document();
+    let ids = parser.head_links()?;
+    Some(
+      document
+        .select(selector!("link"))
+        .filter(|link| ids.contains(&link.id()))
+        .filter_map(|link| link.value().attr("href"))
+        .map(String::from)
+        .collect(),
+    )
+  }
+
+  #[test]
+  fn closes_head_at_tags() {
+    assert_eq!(
+      head_links(r#"<head><link rel="icon" href="/a.png"></head><link rel="icon" href="/b.png">"#),
+      Some(vec!["/a.png".to_string()])
+    );
+    assert_eq!(
+      head_links(r#"<link rel="icon" href="/a.png"><BODY><link rel="icon" href="/b.png">"#),
+      Some(vec!["/a.png".to_string()])
+    );
+    assert_eq!(head_links(r#"<head><link rel="icon" href="/a.png">"#), None);
+  }
+
+  #[test]
+  fn ignores_tags_in_scripts_comments_and_attributes() {
+    assert_eq!(
+      head_links(
+        r#"<head>
+          <script>var s = '<body>' + "</head>";</script>
+          <style>/* </head> */</style>
+          <!-- <body> -->
+          <meta name="description" content="</head><body>">
+          <link rel="icon" href="/a.png">
+        </head>"#
+      ),
+      Some(vec!["/a.png".to_string()])
+    );
+  }
+
+  #[test]
+  fn keeps_links_after_stray_elements() {
+    assert_eq!(
+      head_links(
+        r#"<head><title>x</title><div>oops</div><link rel="icon" href="/a.png"></head>
+        <body><link rel="icon" href="/b.png"></body>"#
+      ),
+      Some(vec!["/a.png".to_string()])
+    );
+  }
+
+  async fn parse_in_chunks(html: &str, chunk_size: usize, body_scan_bytes: usize) -> Vec<String> {
+    let url = Url::parse("https://example.com/").unwrap();
+    let chunks = html
+      .as_bytes()
+      .chunks(chunk_size)
+      .map(|chunk| Ok(chunk.to_vec()))
+      .collect::<Vec<_>>();
+    let (sender, receiver) = oneshot::channel();
+
+    let logo = parse_page(
+      &url,
+      stream::iter(chunks),
+      sender,
+      body_scan_bytes,
+      &LogoHeuristics::default(),
+      0,
+      |_| false,
+    )
+    .await;
+    assert!(logo.is_err());
+
+    receiver
+      .await
+      .unwrap()
+      .links
+      .into_iter()
+      .map(|link| link.href)
+      .collect()
+  }
+
+  #[tokio::test]
+  async fn parses_tiny_chunks() {
+    let html = r#"<html><head>
+      <script>document.write('<body>')</script>
+      <link rel="icon" href="/a.png">
+      <div>oops</div>
+      <link rel="apple-touch-icon" href="/b.png">
+    </head><body><p>hi</p><link rel="icon" href="/c.png"></body></html>"#;
+
+    for chunk_size in [1, 2, 3, 7, html.len()] {
+      assert_eq!(
+        parse_in_chunks(html, chunk_size, 0).await,
+        vec!["/a.png", "/b.png"],
+        "chunks of {} bytes",
+        chunk_size
+      );
+    }
+
+    assert_eq!(
+      parse_in_chunks(html, 1, 1024).await,
+      vec!["/a.png", "/b.png", "/c.png"]
+    );
+  }
+}
@@ -0,0 +1,192 @@
+use scraper::{ElementRef, Html};
+use url::Url;
+
+/// Meta refreshes with a longer delay are usually session timeouts, not landing pages
+const MAX_REFRESH_DELAY: f64 = 5.0;
+/// Only pages with (almost) no visible text are treated as script redirects
+const MAX_SCRIPT_REDIRECT_TEXT: usize = 200;
+
+/// Detects a landing page that only redirects somewhere else on the client,
+/// either through `<meta http-equiv="refresh">` or a lone `window.location=` script.
+pub fn parse_client_redirect(url: &Url, html: &str) -> Option<Url> {
+  let document = Html::parse_document(html);
+
+  // pages that declare their own icons don't need to be followed
+  if document
+    .select(selector!(
+      "link[rel~='icon']",
+      "link[rel~='apple-touch-icon']",
+      "link[rel~='apple-touch-icon-precomposed']",
+      "link[rel~='manifest']"
+    ))
+    .next()
+    .is_some()
+  {
+    return None;
+  }
+
+  let refresh = document
+    .select(selector!("meta[http-equiv][content]"))
+    .filter(|meta| {
+      meta
+        .value()
+        .attr("http-equiv")
+        .is_some_and(|http_equiv| http_equiv.eq_ignore_ascii_case("refresh"))
+    })
+    .filter_map(|meta| meta.value().attr("content"))
+    .filter_map(parse_refresh)
+    .find_map(|(delay, target)| {
+      if delay <= MAX_REFRESH_DELAY {
+        Some(target)
+      } else {
+        None
+      }
+    });
+
+  if let Some(target) = refresh {
+    return url.join(target.trim()).ok();
+  }
+
+  let body_text = document
+    .select(selector!("body"))
+    .map(visible_text)
+    .collect::<String>();
+
+  if body_text.trim().len() > MAX_SCRIPT_REDIRECT_TEXT {
+    return None;
+  }
+
+  document
+    .select(selector!("script:not([src])"))
+    .find_map(|script| {
+      let script = script.text().collect::<String>();
+
+      // `location` has to be the variable itself, not e.g. `geolocation` or
+      // `config.location`, and not a local `var location = ...`
+      regex!(
+        r#"(?:^|[^\w$.])(?:(?P<declaration>(?:var|let|const)\s+)|(?:window|document|top|self)\.)?location(?:\.href)?\s*=\s*(?:'([^']+)'|"([^"]+)")|(?:^|[^\w$.])(?:(?:window|document|top|self)\.)?location\.(?:replace|assign)\(\s*(?:'([^']+)'|"([^"]+)")\s*\)"#
+      )
+      .captures_iter(&script)
+      .filter(|captures| captures.name("declaration").is_none())
+      .find_map(|captures| captures.iter().skip(2).flatten().next())
+      .map(|target| target.as_str().to_string())
+    })
+    .and_then(|target| url.join(target.trim()).ok())
+}
+
+/// The text of the element, without the code of its scripts and styles
+fn visible_text(elem_ref: ElementRef<'_>) -> String {
+  elem_ref
+    .descendants()
+    .filter_map(|node| node.value().as_text().map(|text| (node, text)))
+    .filter(|(node, _)| {
+      !node
+        .ancestors()
+        .filter_map(ElementRef::wrap)
+        .any(|ancestor| {
+          matches!(
+            ancestor.value().name(),
+            "script" | "style" | "noscript" | "template"
+          )
+        })
+    })
+    .map(|(_, text)| &**text)
+    .collect()
+}
+
+/// Parses a `<delay>; url=<target>` refresh declaration
+fn parse_refresh(content: &str) -> Option<(f64, &str)> {
+  let content = content.trim_start();
+  let delay_end = content
+    .find(|c: char| !c.is_ascii_digit() && c != '.')
+    .unwrap_or(content.len());
+  let delay = content[..delay_end].parse::<f64>().ok()?;
+
+  let mut target = content[delay_end..]
+    .trim_start()
+    .trim_start_matches([';', ','])
+    .trim_start();
+
+  if target
+    .get(..3)
+    .is_some_and(|prefix| prefix.eq_ignore_ascii_case("url"))
+  {
+    if let Some(rest) = target[3..].trim_start().strip_prefix('=') {
+      target = rest;
+    }
+  }
+
+  let target = target.trim().trim_matches(['\'', '"']).trim();
+
+  if target.is_empty() {
+    None
+  } else {
+    Some((delay, target))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn redirect(script: &str) -> Option<String> {
+    let url = Url::parse("https://example.com/").unwrap();
+    let html = format!("<html><body><script>{}</script></body></html>", script);
+    parse_client_redirect(&url, &html).map(|url| url.to_string())
+  }
+
+  #[test]
+  fn follows_location_assignments() {
+    assert_eq!(
+      redirect(r#"window.location = "/home";"#).as_deref(),
+      Some("https://example.com/home")
+    );
+    assert_eq!(
+      redirect("location.href='/a'").as_deref(),
+      Some("https://example.com/a")
+    );
+    assert_eq!(
+      redirect(r#"if (x) { top.location.replace("/b") }"#).as_deref(),
+      Some("https://example.com/b")
+    );
+  }
+
+  #[test]
+  fn ignores_other_location_variables() {
+    assert_eq!(redirect(r#"var geolocation = "/x";"#), None);
+    assert_eq!(redirect(r#"var location = "/x";"#), None);
+    assert_eq!(redirect(r#"const apiLocation = "/x";"#), None);
+    assert_eq!(redirect(r#"config.location = "/x";"#), None);
+    assert_eq!(
+      redirect(r#"let location = "/x"; window.location = "/y";"#).as_deref(),
+      Some("https://example.com/y")
+    );
+  }
+
+  #[test]
+  fn ignores_code_in_visible_text() {
+    let script = format!(
+      "var languages = {:?}; window.location = '/en/';",
+      ["en", "de", "fr", "es", "it", "nl", "pt", "ja", "zh", "ko"].repeat(10)
+    );
+    assert!(script.len() > MAX_SCRIPT_REDIRECT_TEXT);
+    assert_eq!(
+      redirect(&script).as_deref(),
+      Some("https://example.com/en/")
+    );
+
+    let url = Url::parse("https://example.com/").unwrap();
+    let html = format!(
+      "<body><p>{}</p><script>window.location = '/en/'</script></body>",
+      "Welcome to our site. ".repeat(20)
+    );
+    assert_eq!(parse_client_redirect(&url, &html), None);
+  }
+
+  #[test]
+  fn parses_refresh_declarations() {
+    assert_eq!(parse_refresh("0; url=/next"), Some((0.0, "/next")));
+    assert_eq!(parse_refresh("3;URL='/quoted'"), Some((3.0, "/quoted")));
+    assert_eq!(parse_refresh("5"), None);
+  }
+}
@@ -0,0 +1,404 @@
+use itertools::Itertools;
+use scraper::{node::Element, ElementRef, Html};
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use url::Url;
+
+/// The types whose `logo` is the site's own: organizations (including the
+/// common subtypes) and websites. Logos of anything else, e.g. the employer
+/// of a person or the brand of a product, belong to some other organization.
+const SITE_TYPES: &[&str] = &[
+  "WebSite",
+  "Organization",
+  "Corporation",
+  "OnlineBusiness",
+  "OnlineStore",
+  "LocalBusiness",
+  "Store",
+  "Restaurant",
+  "Hotel",
+  "ProfessionalService",
+  "FinancialService",
+  "BankOrCreditUnion",
+  "InsuranceAgency",
+  "LegalService",
+  "RealEstateAgent",
+  "TravelAgency",
+  "MedicalOrganization",
+  "Hospital",
+  "NGO",
+  "EducationalOrganization",
+  "CollegeOrUniversity",
+  "School",
+  "GovernmentOrganization",
+  "NewsMediaOrganization",
+  "ResearchOrganization",
+  "SportsOrganization",
+  "SportsTeam",
+  "PerformingGroup",
+  "MusicGroup",
+  "Airline",
+  "Consortium",
+  "Cooperative",
+  "LibrarySystem",
+  "Project",
+  "WorkersUnion",
+];
+
+/// Extracts the logos declared through schema.org, either as JSON-LD
+/// `<script type="application/ld+json">` blocks or as `itemprop="logo"` microdata.
+/// Only the logos of top-level organizations and websites (see [`SITE_TYPES`])
+/// and of their `publisher` are used.
+pub fn parse_schema_org_logos(document: &Html, url: &Url) -> Vec<Url> {
+  let mut logos = Vec::new();
+
+  for script in document.select(selector!("script[type='application/ld+json']")) {
+    let json = script.text().collect::<String>();
+
+    let value: Value = match serde_json::from_str(json_ld_body(&json)) {
+      Ok(value) => value,
+      Err(err) => {
+        info!("{}: invalid JSON-LD: {}", url, err);
+        continue;
+      }
+    };
+
+    let mut ids = HashMap::new();
+    collect_ids(&value, &mut ids);
+
+    let mut srcs = Vec::new();
+    collect_logos(&value, &ids, &mut srcs);
+
+    logos.extend(srcs.into_iter().filter_map(|src| url.join(src.trim()).ok()));
+  }
+
+  for elem_ref in document.select(selector!("[itemprop~='logo']")) {
+    if !is_site_microdata(elem_ref) {
+      continue;
+    }
+
+    if let Some(src) = microdata_image(elem_ref) {
+      if let Ok(src) = url.join(src.trim()) {
+        logos.push(src);
+      }
+    }
+  }
+
+  logos.into_iter().unique().collect()
+}
+
+/// Some CMSes wrap the JSON in HTML comments or CDATA markers
+fn json_ld_body(json: &str) -> &str {
+  let json = json.trim();
+  let json = json
+    .strip_prefix("<!--")
+    .and_then(|json| json.strip_suffix("-->"))
+    .unwrap_or(json)
+    .trim();
+
+  json
+    .strip_prefix("//<![CDATA[")
+    .and_then(|json| json.strip_suffix("//]]>"))
+    .unwrap_or(json)
+    .trim()
+}
+
+fn collect_ids<'a>(value: &'a Value, ids: &mut HashMap<&'a str, &'a Map<String, Value>>) {
+  match value {
+    Value::Array(values) => values.iter().for_each(|value| collect_ids(value, ids)),
+    Value::Object(object) => {
+      if let Some(Value::String(id)) = object.get("@id") {
+        // prefer the definition over bare `{"@id": ...}` references
+        if object.len() > 1 || !ids.contains_key(id.as_str()) {
+          ids.insert(id, object);
+        }
+      }
+
+      object.values().for_each(|value| collect_ids(value, ids));
+    }
+    _ => {}
+  }
+}
+
+fn collect_logos<'a>(
+  value: &'a Value,
+  ids: &HashMap<&'a str, &'a Map<String, Value>>,
+  logos: &mut Vec<&'a str>,
+) {
+  match value {
+    Value::Array(values) => values
+      .iter()
+      .for_each(|value| collect_logos(value, ids, logos)),
+    Value::Object(object) => {
+      if let Some(graph) = object.get("@graph") {
+        collect_logos(graph, ids, logos);
+      }
+
+      if !is_type(object, SITE_TYPES) {
+        return;
+      }
+
+      if let Some(logo) = object.get("logo") {
+        image_urls(logo, ids, logos);
+      }
+
+      if let Some(publisher) = object.get("publisher") {
+        publisher_logos(publisher, ids, logos);
+      }
+    }
+    _ => {}
+  }
+}
+
+fn publisher_logos<'a>(
+  value: &'a Value,
+  ids: &HashMap<&'a str, &'a Map<String, Value>>,
+  logos: &mut Vec<&'a str>,
+) {
+  match value {
+    Value::Array(values) => values
+      .iter()
+      .for_each(|value| publisher_logos(value, ids, logos)),
+    Value::Object(object) => {
+      if let Some(logo) = resolve_id(object, ids).and_then(|object| object.get("logo")) {
+        image_urls(logo, ids, logos);
+      }
+    }
+    _ => {}
+  }
+}
+
+/// Resolves a bare `{"@id": ...}` reference to its definition
+fn resolve_id<'a>(
+  object: &'a Map<String, Value>,
+  ids: &HashMap<&'a str, &'a Map<String, Value>>,
+) -> Option<&'a Map<String, Value>> {
+  match object.get("@id") {
+    Some(Value::String(id)) if object.len() == 1 => ids.get(id.as_str()).copied(),
+    _ => Some(object),
+  }
+}
+
+/// Resolves a schema.org `URL` or `ImageObject` value (or an array of them)
+fn image_urls<'a>(
+  value: &'a Value,
+  ids: &HashMap<&'a str, &'a Map<String, Value>>,
+  urls: &mut Vec<&'a str>,
+) {
+  match value {
+    Value::String(url) => urls.push(url),
+    Value::Array(values) => values.iter().for_each(|value| image_urls(value, ids, urls)),
+    Value::Object(object) => {
+      let object = match resolve_id(object, ids) {
+        Some(object) => object,
+        None => return,
+      };
+
+      if let Some(url) = ["contentUrl", "url"]
+        .iter()
+        .find_map(|key| object.get(*key).and_then(Value::as_str))
+      {
+        urls.push(url);
+      }
+    }
+    _ => {}
+  }
+}
+
+/// Whether one of the types is in `types`, ignoring the `https://schema.org/`
+/// (or `schema:`) prefix
+fn has_type<'a>(kinds: impl IntoIterator<Item = &'a str>, types: &[&str]) -> bool {
+  kinds
+    .into_iter()
+    .filter_map(|kind| kind.trim_end_matches('/').rsplit(['/', ':']).next())
+    .any(|kind| types.contains(&kind))
+}
+
+fn is_type(object: &Map<String, Value>, types: &[&str]) -> bool {
+  match object.get("@type") {
+    Some(Value::String(kind)) => has_type([kind.as_str()], types),
+    Some(Value::Array(kinds)) => has_type(kinds.iter().filter_map(Value::as_str), types),
+    _ => false,
+  }
+}
+
+/// Whether a microdata `logo` belongs to the site: its scope is a top-level
+/// organization or website, or the `publisher` of one
+fn is_site_microdata(elem_ref: ElementRef<'_>) -> bool {
+  let is_site_scope = |scope: &Element| {
+    scope
+      .attr("itemtype")
+      .is_some_and(|types| has_type(types.split_ascii_whitespace(), SITE_TYPES))
+  };
+
+  let mut scopes = elem_ref
+    .ancestors()
+    .filter_map(ElementRef::wrap)
+    .map(|ancestor| ancestor.value())
+    .filter(|ancestor| ancestor.attr("itemscope").is_some());
+
+  let scope = match scopes.next() {
+    Some(scope) if is_site_scope(scope) => scope,
+    _ => return false,
+  };
+
+  match scopes.next() {
+    None => true,
+    Some(parent) => {
+      scope.attr("itemprop").is_some_and(|props| {
+        props
+          .split_ascii_whitespace()
+          .any(|prop| prop == "publisher")
+      }) && is_site_scope(parent)
+        && scopes.next().is_none()
+    }
+  }
+}
+
+fn microdata_image(elem_ref: ElementRef<'_>) -> Option<&str> {
+  let elem = elem_ref.value();
+
+  match elem.name() {
+    "img" | "source" => elem.attr("src"),
+    "a" | "link" | "area" => elem.attr("href"),
+    "meta" => elem.attr("content"),
+    "object" => elem.attr("data"),
+    // an ImageObject scope
+    _ if elem.attr("itemscope").is_some() => elem_ref
+      .select(selector!(
+        "[itemprop~='contentUrl']",
+        "[itemprop~='url']",
+        "img[itemprop~='image']"
+      ))
+      .find_map(microdata_image),
+    _ => None,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn logos(html: &str) -> Vec<String> {
+    let url = Url::parse("https://example.com/").unwrap();
+
+    parse_schema_org_logos(&Html::parse_document(html), &url)
+      .into_iter()
+      .map(String::from)
+      .collect()
+  }
+
+  #[test]
+  fn skips_foreign_json_ld_logos() {
+    assert_eq!(
+      logos(
+        r#"<script type="application/ld+json">{
+          "@graph": [
+            { "@type": "Organization", "logo": { "@type": "ImageObject", "url": "/logo.png" } },
+            { "@type": "Product", "logo": "/product.png" },
+            { "@type": "Offer", "seller": { "logo": "/seller.png" } }
+          ]
+        }</script>"#
+      ),
+      vec!["https://example.com/logo.png"]
+    );
+  }
+
+  #[test]
+  fn skips_logos_of_other_organizations() {
+    assert_eq!(
+      logos(
+        r#"<script type="application/ld+json">{
+          "@type": "Person",
+          "name": "Me Example",
+          "worksFor": { "@type": "Organization", "logo": "https://employer.example/logo.png" },
+          "alumniOf": { "@type": "CollegeOrUniversity", "logo": "/university.png" }
+        }</script>"#
+      ),
+      Vec::<String>::new()
+    );
+  }
+
+  #[test]
+  fn uses_publisher_logos() {
+    assert_eq!(
+      logos(
+        r##"<script type="application/ld+json">[
+          {
+            "@type": "WebSite",
+            "publisher": { "@id": "#org" },
+            "author": { "@type": "Organization", "logo": "/author.png" }
+          },
+          { "@type": "schema:Corporation", "@id": "#org", "logo": "/logo.svg", "parentOrganization": { "@type": "Organization", "logo": "/parent.png" } }
+        ]</script>
+        <script type="application/ld+json">{
+          "@type": "NewsArticle",
+          "publisher": { "@type": "Organization", "logo": "/article.png" }
+        }</script>"##
+      ),
+      vec!["https://example.com/logo.svg"]
+    );
+  }
+
+  #[test]
+  fn uses_microdata_publisher_logos() {
+    assert_eq!(
+      logos(
+        r#"<div itemscope itemtype="https://schema.org/WebSite">
+          <div itemprop="publisher" itemscope itemtype="https://schema.org/Organization">
+            <img itemprop="logo" src="/logo.png">
+          </div>
+        </div>
+        <div itemscope itemtype="https://schema.org/Person">
+          <div itemprop="worksFor" itemscope itemtype="https://schema.org/Organization">
+            <img itemprop="logo" src="/employer.png">
+          </div>
+        </div>
+        <img itemprop="logo" src="/unscoped.png">"#
+      ),
+      vec!["https://example.com/logo.png"]
+    );
+  }
+
+  #[test]
+  fn skips_foreign_microdata_logos() {
+    assert_eq!(
+      logos(
+        r#"<div itemscope itemtype="https://schema.org/Organization">
+          <img itemprop="logo" src="/logo.png">
+        </div>
+        <div itemscope itemtype="https://schema.org/Product">
+          <img itemprop="logo" src="/product.png">
+          <div itemprop="brand" itemscope itemtype="https://schema.org/Brand">
+            <img itemprop="logo" src="/brand.png">
+          </div>
+          <div itemprop="manufacturer" itemscope itemtype="https://schema.org/Organization">
+            <div itemprop="logo" itemscope itemtype="https://schema.org/ImageObject">
+              <meta itemprop="contentUrl" content="/manufacturer.png">
+            </div>
+          </div>
+        </div>
+        <div itemscope itemtype="https://schema.org/Car">
+          <div itemprop="manufacturer" itemscope itemtype="https://schema.org/Organization">
+            <img itemprop="logo" src="/maker.png">
+          </div>
+        </div>"#
+      ),
+      vec!["https://example.com/logo.png"]
+    );
+  }
+
+  #[test]
+  fn resolves_microdata_image_objects() {
+    assert_eq!(
+      logos(
+        r#"<div itemscope itemtype="https://schema.org/Organization">
+          <div itemprop="logo" itemscope itemtype="https://schema.org/ImageObject">
+            <meta itemprop="contentUrl" content="/logo.svg">
+          </div>
+        </div>"#
+      ),
+      vec!["https://example.com/logo.svg"]
+    );
+  }
+}
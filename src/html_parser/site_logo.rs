@@ -1,51 +1,362 @@
-use crate::{utils::encode_svg, Icon, IconKind};
-use futures::{Stream, StreamExt};
-use html5ever::{
-  driver,
-  tendril::{Tendril, TendrilSink},
+use super::{
+  background_image_url, best_image_source, embedded_image_source, parse_h_card_logos,
+  parse_schema_org_logos, parse_style_blocks, standalone_svg, wrapped_svg_image, StyleRule,
 };
-use scraper::{ElementRef, Html};
-use std::error::Error;
+use crate::{
+  utils::{compact_text, encode_svg, fold_text, fold_words},
+  IconCandidate, LogoCondition, LogoHeuristics, ScoredLogo,
+};
+use itertools::Itertools;
+use regex::Regex;
+use scraper::{ElementRef, Html, Selector};
+use std::cell::RefCell;
+use std::cmp::Reverse;
+use std::collections::HashMap;
 use std::iter;
 use tldextract::TldOption;
 use url::Url;
 
-pub async fn parse_site_logo(
+/// Whether the best logo of a partially parsed page is good enough to stop
+/// reading the page. An `<svg>` has to be complete, so it isn't cut off.
+pub fn has_decisive_logo(
+  document: &Html,
   url: &Url,
-  mut body: impl Stream<Item = Result<Vec<u8>, String>> + Unpin,
+  heuristics: &LogoHeuristics,
   is_blacklisted: impl Fn(&Url) -> bool,
-) -> Result<Icon, Box<dyn Error>> {
-  let mut parser = driver::parse_document(Html::new_document(), Default::default());
-  while let Some(data) = body.next().await {
-    if let Ok(data) = Tendril::try_from_byte_slice(&data?) {
-      parser.process(data)
+) -> bool {
+  let decisive_weight = match heuristics.decisive_weight {
+    Some(decisive_weight) => decisive_weight,
+    None => return false,
+  };
+
+  let logo = match score_site_logos(document, url, heuristics, &[], is_blacklisted)
+    .into_iter()
+    .next()
+  {
+    Some(logo) if logo.weight >= decisive_weight => logo,
+    _ => return false,
+  };
+
+  match (logo.tag.as_deref(), &logo.selector_path) {
+    (Some("svg"), Some(selector_path)) => Selector::parse(selector_path)
+      .ok()
+      .and_then(|selector| document.select(&selector).next())
+      // the parser has moved past the element, when a node was added after it
+      .is_some_and(|svg| {
+        svg.next_sibling().is_some() || svg.ancestors().any(|node| node.next_sibling().is_some())
+      }),
+    _ => true,
+  }
+}
+
+/// A [`LogoRule`] with its selector parsed
+enum CompiledRule<'a> {
+  Inside(Selector),
+  FirstMatch,
+  AttributeMatches(&'a [String], &'a Regex),
+  AttributeContainsKeyword(&'a [String]),
+  AttributeContainsSiteName(&'a [String]),
+}
+
+/// The image elements that can be a logo
+const IMAGE_ELEMENTS: &[&str] = &["img", "svg", "object", "embed"];
+
+/// The attributes of image elements a keyword makes them a candidate through
+const KEYWORD_IMAGE_ATTRS: &[&str] = &[
+  "alt",
+  "src",
+  "data",
+  "srcset",
+  "data-src",
+  "data-lazy-src",
+  "data-original",
+  "class",
+  "id",
+];
+/// The attributes of the elements containing an image, or with a background
+/// image, a keyword makes them a candidate through
+const KEYWORD_CONTAINER_ATTRS: &[&str] = &["class", "id"];
+
+/// Whether one of the words of the text starts with one of the folded
+/// keywords. The rest of the word has to be another word, e.g. `logotype`, and
+/// not an ending like in `logos`, `logout` or `marquee`. ASCII keywords can
+/// also end a word, or be followed by anything but an `s` inside it, e.g.
+/// `sitelogo` or `companylogo.png`. Keywords in scripts without case (e.g. `ロゴ`)
+/// can be anywhere in a word, as these aren't separated by spaces.
+fn contains_keyword(text: &str, keywords: &[String]) -> bool {
+  let words = fold_words(text);
+
+  keywords.iter().any(|keyword| {
+    let cased = keyword.chars().next().is_some_and(char::is_lowercase);
+
+    words.iter().any(|word| {
+      if !cased {
+        return word.contains(keyword.as_str());
+      }
+
+      if word
+        .strip_prefix(keyword.as_str())
+        .is_some_and(|rest| rest.is_empty() || rest.chars().count() >= 3)
+      {
+        return true;
+      }
+
+      keyword.is_ascii()
+        && word
+          .match_indices(keyword.as_str())
+          .any(|(start, _)| start > 0 && !word[start + keyword.len()..].starts_with('s'))
+    })
+  })
+}
+
+/// The names the site goes by, compacted: the segments of the domain's name
+/// (decoded when it's an IDN), the `og:site_name` and the name in the `<title>`.
+/// The title is only used when it matches one of the others, as it's often
+/// the name of the page instead, e.g. `Home`.
+fn site_names(document: &Html, url: &Url) -> Vec<String> {
+  let domain_names = url
+    .domain()
+    .and_then(|domain| TldOption::default().build().extract(domain).ok()?.domain)
+    .map(|name| {
+      let (name, _) = idna::domain_to_unicode(&name);
+      name.split('-').map(String::from).collect::<Vec<_>>()
+    })
+    .unwrap_or_default();
+
+  let og_site_name = document
+    .select(selector!("meta[property='og:site_name'][content]"))
+    .filter_map(|meta| meta.value().attr("content"))
+    .map(String::from)
+    .collect::<Vec<_>>();
+
+  // e.g. "Page - Site" or "Site | Tagline"
+  let title = document
+    .select(selector!("head title, html > title"))
+    .next()
+    .map(|title| title.text().collect::<String>())
+    .map(|title| {
+      let segments = regex!(r"\s+[|\-–—·•:]\s+")
+        .split(title.trim())
+        .collect::<Vec<_>>();
+
+      match segments[..] {
+        [] => Vec::new(),
+        [title] => vec![title.to_string()],
+        [first, .., last] => vec![first.to_string(), last.to_string()],
+      }
+    })
+    .unwrap_or_default();
+
+  // single letters are contained in most text
+  let compact = |names: Vec<String>| {
+    names
+      .iter()
+      .map(|name| compact_text(name))
+      .filter(|name| name.chars().count() >= 2)
+      .collect::<Vec<_>>()
+  };
+
+  let names = compact(domain_names)
+    .into_iter()
+    .chain(compact(og_site_name))
+    .collect::<Vec<_>>();
+  let title = compact(title)
+    .into_iter()
+    .filter(|title| {
+      names
+        .iter()
+        .any(|name| title.contains(name.as_str()) || name.contains(title.as_str()))
+    })
+    .collect::<Vec<_>>();
+
+  names.into_iter().chain(title).unique().collect()
+}
+
+fn parse_selector(selector: &str) -> Option<Selector> {
+  match Selector::parse(selector) {
+    Ok(selector) => Some(selector),
+    Err(err) => {
+      info!("invalid logo selector {:?}: {:?}", selector, err);
+      None
     }
   }
+}
+
+/// Combines the valid selectors into one, `None` when there are none
+fn parse_selectors<'a>(selectors: impl IntoIterator<Item = &'a String>) -> Option<Selector> {
+  let selector = selectors
+    .into_iter()
+    .filter(|selector| parse_selector(selector).is_some())
+    .join(",");
+
+  if selector.is_empty() {
+    None
+  } else {
+    parse_selector(&selector)
+  }
+}
+
+/// A CSS selector path to the element, with `:nth-of-type` for elements
+/// that have siblings of the same type
+fn selector_path(elem_ref: ElementRef) -> String {
+  let mut segments = iter::once(elem_ref)
+    .chain(elem_ref.ancestors().filter_map(ElementRef::wrap))
+    .map(|elem_ref| {
+      let name = elem_ref.value().name();
+      let siblings = elem_ref
+        .parent()
+        .into_iter()
+        .flat_map(|parent| parent.children())
+        .filter_map(ElementRef::wrap)
+        .filter(|sibling| sibling.value().name() == name)
+        .collect::<Vec<_>>();
+
+      match siblings
+        .iter()
+        .position(|sibling| sibling.id() == elem_ref.id())
+      {
+        Some(i) if siblings.len() > 1 => format!("{}:nth-of-type({})", name, i + 1),
+        _ => name.to_string(),
+      }
+    })
+    .collect::<Vec<_>>();
+
+  segments.reverse();
+  segments.join(" > ")
+}
+
+/// The elements of the page that look like a site logo, with the highest
+/// scoring one first
+pub fn site_logo_candidates(
+  document: &Html,
+  url: &Url,
+  heuristics: &LogoHeuristics,
+  stylesheets: &[StyleRule],
+  is_blacklisted: impl Fn(&Url) -> bool,
+) -> Vec<IconCandidate> {
+  score_site_logos(document, url, heuristics, stylesheets, is_blacklisted)
+    .iter()
+    .map(ScoredLogo::to_candidate)
+    .collect()
+}
 
-  let document = parser.finish();
+/// Scores the elements of the page that look like a site logo, returning
+/// them from highest to lowest weight. Background images are looked up in the
+/// inline styles, the `<style>` blocks and the given linked `stylesheets`.
+pub fn score_site_logos(
+  document: &Html,
+  url: &Url,
+  heuristics: &LogoHeuristics,
+  stylesheets: &[StyleRule],
+  is_blacklisted: impl Fn(&Url) -> bool,
+) -> Vec<ScoredLogo> {
+  let image_selector = parse_selectors(&heuristics.selectors);
+  let background_selector = parse_selectors(&heuristics.background_selectors);
+  let keywords = heuristics
+    .keywords
+    .iter()
+    .map(|keyword| fold_text(keyword))
+    .filter(|keyword| !keyword.is_empty())
+    .collect::<Vec<_>>();
+  let has_keyword = |elem_ref: &ElementRef, attrs: &[&str]| {
+    attrs.iter().any(|attr| {
+      elem_ref
+        .value()
+        .attr(attr)
+        .is_some_and(|value| contains_keyword(value, &keywords))
+    })
+  };
+  // every element is checked as a container, and again as an ancestor of each image
+  let containers = RefCell::new(HashMap::new());
+  let is_keyword_container = |elem_ref: &ElementRef| {
+    *containers
+      .borrow_mut()
+      .entry(elem_ref.id())
+      .or_insert_with(|| has_keyword(elem_ref, KEYWORD_CONTAINER_ATTRS))
+  };
+
+  // linked stylesheets come before the `<style>` blocks, the last matching rule wins
+  let style_blocks = parse_style_blocks(document, url);
+  let style_rules = stylesheets.iter().chain(&style_blocks).collect::<Vec<_>>();
+
+  let background_image = |elem_ref: &ElementRef| {
+    elem_ref
+      .value()
+      .attr("style")
+      .and_then(|style| background_image_url(style, url))
+      .or_else(|| {
+        style_rules
+          .iter()
+          .rev()
+          .filter(|rule| rule.selector.matches(elem_ref))
+          .find_map(|rule| rule.background_image())
+      })
+  };
+
+  let rules = heuristics
+    .rules
+    .iter()
+    .filter_map(|rule| {
+      let condition = match &rule.condition {
+        LogoCondition::Inside(selector) => CompiledRule::Inside(parse_selector(selector)?),
+        LogoCondition::FirstMatch => CompiledRule::FirstMatch,
+        LogoCondition::AttributeMatches { attrs, pattern } => {
+          CompiledRule::AttributeMatches(attrs, pattern)
+        }
+        LogoCondition::AttributeContainsKeyword { attrs } => {
+          CompiledRule::AttributeContainsKeyword(attrs)
+        }
+        LogoCondition::AttributeContainsSiteName { attrs } => {
+          CompiledRule::AttributeContainsSiteName(attrs)
+        }
+      };
+
+      Some((condition, rule))
+    })
+    .collect::<Vec<_>>();
+
+  let site_names = site_names(document, url);
 
   let mut logos: Vec<_> = document
-    .select(selector!(
-      "a[href='/'] img, a[href='/'] svg",
-      "header img, header svg",
-      "img[src*=logo]",
-      "img[alt*=logo], svg[alt*=logo]",
-      "*[class*=logo] img, *[class*=logo] svg",
-      "*[id*=logo] img, *[id*=logo] svg",
-      "img[class*=logo], svg[class*=logo]",
-      "img[id*=logo], svg[id*=logo]",
-    ))
+    .root_element()
+    .descendants()
+    .filter_map(ElementRef::wrap)
+    .filter_map(|elem_ref| {
+      let is_image = IMAGE_ELEMENTS.contains(&elem_ref.value().name())
+        && (image_selector
+          .as_ref()
+          .is_some_and(|selector| selector.matches(&elem_ref))
+          || has_keyword(&elem_ref, KEYWORD_IMAGE_ATTRS)
+          || elem_ref
+            .ancestors()
+            .filter_map(ElementRef::wrap)
+            .any(|ancestor| is_keyword_container(&ancestor)));
+
+      if is_image {
+        Some((elem_ref, None))
+      } else if background_selector
+        .as_ref()
+        .is_some_and(|selector| selector.matches(&elem_ref))
+        || is_keyword_container(&elem_ref)
+      {
+        Some((elem_ref, Some(background_image(&elem_ref)?)))
+      } else {
+        None
+      }
+    })
     .enumerate()
-    .filter_map(|(i, elem_ref)| {
+    .filter_map(|(i, (elem_ref, background))| {
       let elem = elem_ref.value();
-      let ancestors = elem_ref
+      let ancestor_refs = elem_ref
         .ancestors()
-        .map(ElementRef::wrap)
-        .flatten()
+        .filter_map(ElementRef::wrap)
+        .collect::<Vec<_>>();
+      let ancestors = ancestor_refs
+        .iter()
         .map(|elem_ref| elem_ref.value())
         .collect::<Vec<_>>();
 
-      let skip_classnames = regex!("menu|search");
+      let skip_classnames = &heuristics.skip_pattern;
       let should_skip = ancestors.iter().any(|ancestor| {
         ancestor
           .attr("class")
@@ -62,65 +373,50 @@ pub async fn parse_site_logo(
         return None;
       }
 
-      let mut weight = 0;
-
-      // if in the header
-      if ancestors.iter().any(|element| element.name() == "header") {
-        weight += 2;
-      }
-
-      if i == 0 {
-        weight += 1;
-      }
-
-      let mentions = |attr_name, is_match: Box<dyn Fn(&str) -> bool>| {
+      let mentions = |attrs: &[String], is_match: &dyn Fn(&str) -> bool| {
         ancestors.iter().chain(iter::once(&elem)).any(|ancestor| {
-          ancestor
-            .attr(attr_name)
-            .map(|attr| is_match(&attr.to_lowercase()))
-            .unwrap_or(false)
+          attrs
+            .iter()
+            .any(|attr_name| ancestor.attr(attr_name).is_some_and(is_match))
         })
       };
 
-      if mentions("href", Box::new(|attr| attr == "/")) {
-        weight += 5;
-      };
-
-      let mentions_logo = |attr_name| {
-        mentions(
-          attr_name,
-          Box::new(|attr| regex!("logo([^s]|$)").is_match(attr)),
-        )
-      };
-
-      if mentions_logo("class") || mentions_logo("id") {
-        weight += 3;
-      }
-      if mentions_logo("alt") {
-        weight += 2;
-      }
-      if mentions_logo("src") {
-        weight += 1;
-      }
-
-      if let Some(site_name) = url
-        .domain()
-        .and_then(|domain| TldOption::default().build().extract(domain).unwrap().domain)
-      {
-        // if the alt contains the site_name then highest priority
-        if site_name
-          .to_lowercase()
-          .split('-')
-          .any(|segment| mentions("alt", Box::new(move |attr| attr.contains(segment))))
-        {
-          weight += 10;
-        }
-      }
+      let applied = rules
+        .iter()
+        .filter(|(rule, _)| match rule {
+          CompiledRule::Inside(selector) => ancestor_refs
+            .iter()
+            .any(|ancestor| selector.matches(ancestor)),
+          CompiledRule::FirstMatch => i == 0,
+          CompiledRule::AttributeMatches(attrs, pattern) => {
+            mentions(attrs, &|attr| pattern.is_match(&attr.to_lowercase()))
+          }
+          CompiledRule::AttributeContainsKeyword(attrs) => {
+            mentions(attrs, &|attr| contains_keyword(attr, &keywords))
+          }
+          CompiledRule::AttributeContainsSiteName(attrs) => mentions(attrs, &|attr| {
+            let attr = compact_text(attr);
+            site_names.iter().any(|site_name| attr.contains(site_name))
+          }),
+        })
+        .map(|(_, rule)| (rule.name.clone(), rule.weight))
+        .collect::<Vec<_>>();
 
-      let href = if elem.name() == "svg" {
-        Some(Url::parse(&encode_svg(&elem_ref.html())).unwrap())
+      let href = if background.is_some() {
+        background
       } else {
-        elem.attr("src").and_then(|href| url.join(&href).ok())
+        match elem.name() {
+          "svg" => Some(wrapped_svg_image(elem_ref, url).unwrap_or_else(|| {
+            Url::parse(&encode_svg(&standalone_svg(
+              elem_ref,
+              document,
+              &style_rules,
+            )))
+            .unwrap()
+          })),
+          "img" => best_image_source(elem_ref, url),
+          _ => embedded_image_source(elem_ref, url),
+        }
       };
 
       if let Some(href) = &href {
@@ -129,29 +425,115 @@ pub async fn parse_site_logo(
         }
       }
 
-      href.map(|href| (href, elem_ref, weight))
+      let alt = match elem.name() {
+        "svg" => elem_ref
+          .children()
+          .filter_map(ElementRef::wrap)
+          .find(|child| child.value().name() == "title")
+          .map(|title| title.text().collect::<String>()),
+        "img" => elem.attr("alt").map(|alt| alt.to_string()),
+        // background images and embeds are usually labelled, or have (fallback) text
+        _ => elem
+          .attr("aria-label")
+          .or_else(|| elem.attr("title"))
+          .map(|label| label.to_string())
+          .or_else(|| {
+            let text = elem_ref.text().collect::<String>();
+            Some(text.trim().to_string()).filter(|text| !text.is_empty())
+          }),
+      };
+
+      href.map(|href| ScoredLogo {
+        href,
+        tag: Some(elem.name().to_string()),
+        selector_path: Some(selector_path(elem_ref)),
+        alt,
+        weight: applied.iter().map(|(_, weight)| weight).sum(),
+        rules: applied,
+      })
     })
     .collect();
 
-  logos.sort_by(|(_, _, a_weight), (_, _, b_weight)| b_weight.cmp(a_weight));
+  let declared = |href: Url, rule: &str, weight: i32| ScoredLogo {
+    href,
+    tag: None,
+    selector_path: None,
+    alt: None,
+    weight,
+    rules: vec![(rule.to_string(), weight)],
+  };
 
-  // prefer <img> over svg
-  let mut prev_weight = None;
-  for (href, elem_ref, weight) in &logos {
-    if let Some(prev_weight) = prev_weight {
-      if weight != prev_weight {
-        break;
-      }
-    }
-    prev_weight = Some(weight);
+  // logos declared through schema.org are high-confidence
+  logos.extend(
+    parse_schema_org_logos(document, url)
+      .into_iter()
+      .filter(|href| !is_blacklisted(href))
+      .map(|href| declared(href, "schema_org", heuristics.schema_org_weight)),
+  );
+
+  // as are the h-card logos of IndieWeb sites, a personal photo slightly less so
+  let (h_card_logos, h_card_photos) = parse_h_card_logos(document, url);
+  logos.extend(
+    h_card_logos
+      .into_iter()
+      .map(|href| (href, "h_card_logo", heuristics.h_card_logo_weight))
+      .chain(
+        h_card_photos
+          .into_iter()
+          .map(|href| (href, "h_card_photo", heuristics.h_card_photo_weight)),
+      )
+      .filter(|(href, _, _)| !is_blacklisted(href))
+      .map(|(href, rule, weight)| declared(href, rule, weight)),
+  );
+  // prefer <img> over svg, when the weights are equal
+  logos.sort_by_key(|logo| (Reverse(logo.weight), logo.tag.as_deref() == Some("svg")));
 
-    if elem_ref.value().name() == "img" {
-      return Icon::load(href.clone(), IconKind::SiteLogo, None).await;
+  logos
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn matches(text: &str) -> bool {
+    let keywords = ["logo", "marque", "лого", "ロゴ"].map(String::from);
+    contains_keyword(text, &keywords)
+  }
+
+  #[test]
+  fn matches_keywords_in_words() {
+    for text in [
+      "logo",
+      "site-logo",
+      "siteLogo",
+      "Site_Logo_2x",
+      "sitelogo",
+      "mainlogo",
+      "/img/companylogo.png",
+      "logotype",
+      "logo-dark.svg",
+      "Marque",
+      "Логотип",
+      "ロゴ画像",
+      "会社ロゴ",
+    ] {
+      assert!(matches(text), "{}", text);
     }
   }
 
-  match logos.into_iter().next() {
-    Some((href, _, _)) => Icon::load(href.clone(), IconKind::SiteLogo, None).await,
-    None => Err("No site logo found".into()),
+  #[test]
+  fn skips_other_words() {
+    for text in [
+      "logos",
+      "partner-logos",
+      "sitelogos",
+      "logout",
+      "marquee",
+      "dialog",
+      "blog",
+      "лого́с",
+    ] {
+      assert!(!matches(text), "{}", text);
+    }
   }
 }
@@ -0,0 +1,476 @@
+use crate::IconInfo;
+use scraper::{node::Element, ElementRef, Html};
+use std::cmp::Ordering;
+use url::Url;
+
+/// An image candidate of a `srcset` attribute
+#[derive(Debug, Clone, PartialEq)]
+pub struct SrcsetCandidate {
+  pub href: Url,
+  /// The pixel density the image is meant for, `None` for width descriptors
+  /// when the slot width isn't known (and then assumed to be `1x`)
+  pub density: Option<f32>,
+  /// The `w` descriptor
+  pub width: Option<u32>,
+}
+
+/// Parses a `srcset` attribute, e.g. `logo.png 1x, logo@2x.png 2x` or
+/// `logo-300.png 300w, logo-600.png 600w`. The `slot_width` (from the
+/// `sizes` attribute) is used to turn width descriptors into densities.
+pub fn parse_srcset(srcset: &str, slot_width: Option<f32>, base: &Url) -> Vec<SrcsetCandidate> {
+  let mut candidates = Vec::new();
+  let mut rest = srcset;
+
+  loop {
+    rest = rest.trim_start_matches(|c: char| c.is_whitespace() || c == ',');
+    if rest.is_empty() {
+      break;
+    }
+
+    // URLs can contain commas (e.g. data URIs), only a trailing one is a separator
+    let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+    let (href, after) = rest.split_at(end);
+    let (href, descriptors) = if href.ends_with(',') {
+      rest = after;
+      (href.trim_end_matches(','), "")
+    } else {
+      let end = after.find(',').unwrap_or(after.len());
+      rest = &after[end..];
+      (href, after[..end].trim())
+    };
+
+    let href = match base.join(href) {
+      Ok(href) => href,
+      Err(_) => continue,
+    };
+
+    let mut candidate = SrcsetCandidate {
+      href,
+      density: Some(1.0),
+      width: None,
+    };
+
+    for descriptor in descriptors.split_whitespace() {
+      if let Some(density) = descriptor.strip_suffix('x') {
+        candidate.density = density.parse().ok();
+      } else if let Some(width) = descriptor.strip_suffix('w') {
+        candidate.width = width.parse().ok();
+        candidate.density = slot_width
+          .zip(candidate.width)
+          .map(|(slot_width, width)| width as f32 / slot_width);
+      }
+    }
+
+    candidates.push(candidate);
+  }
+
+  candidates
+}
+
+/// The slot width of a `sizes` attribute, when it's in pixels. Only the last
+/// (unconditional) size is used, e.g. `120` for `(max-width: 600px) 80px, 120px`.
+pub fn parse_sizes(sizes: &str) -> Option<f32> {
+  let size = sizes.rsplit(',').next()?.trim();
+  if size.starts_with('(') {
+    return None;
+  }
+
+  size.strip_suffix("px")?.trim().parse().ok()
+}
+
+fn is_vector(href: &Url, mime_type: Option<&str>) -> bool {
+  match mime_type {
+    Some(mime_type) => mime_type.trim().eq_ignore_ascii_case("image/svg+xml"),
+    None => {
+      href.path().to_ascii_lowercase().ends_with(".svg")
+        || (href.scheme() == "data" && href.path().starts_with("image/svg+xml"))
+    }
+  }
+}
+
+/// Attributes lazy-loading libraries keep the real `src` in
+pub const LAZY_SRC_ATTRS: &[&str] = &["data-src", "data-lazy-src", "data-original"];
+/// Attributes lazy-loading libraries keep the real `srcset` in
+pub const LAZY_SRCSET_ATTRS: &[&str] = &["data-srcset", "data-lazy-srcset"];
+
+fn lazy_attr<'a>(elem: &'a Element, attrs: &[&str]) -> Option<&'a str> {
+  attrs
+    .iter()
+    .find_map(|attr| elem.attr(attr))
+    .filter(|value| !value.trim().is_empty())
+}
+
+/// Whether the image is a placeholder shown until the real image is loaded,
+/// e.g. a 1x1 GIF, a blurred preview data URI or a `spacer.gif`
+fn is_placeholder(href: &Url, lazy: bool) -> bool {
+  if href.scheme() == "data" {
+    // blurred previews can be larger, but they're only used with lazy-loading
+    let raster = !href.path().starts_with("image/svg+xml");
+    return (raster && lazy) || href.as_str().len() < 200;
+  }
+
+  href
+    .path_segments()
+    .and_then(|mut segments| segments.next_back())
+    .is_some_and(|name| {
+      regex!(r"(?i)^(blank|spacer|pixel|placeholder|transparent|lazy([-_]?load)?|loading|empty|1x1)([-_]?\d+(x\d+)?)?\.(gif|png|svg|jpe?g|webp)$")
+        .is_match(name)
+    })
+}
+
+/// The image of a `<noscript><img></noscript>` next to the `<img>`, which
+/// lazy-loading libraries add for clients without JavaScript
+fn noscript_fallback(img: ElementRef, base: &Url) -> Option<Url> {
+  let parent = img.parent().and_then(ElementRef::wrap)?;
+  let container = if parent.value().name() == "picture" {
+    parent.parent().and_then(ElementRef::wrap)?
+  } else {
+    parent
+  };
+
+  container
+    .children()
+    .filter_map(ElementRef::wrap)
+    .filter(|sibling| sibling.value().name() == "noscript")
+    .find_map(|noscript| {
+      // with scripting enabled, the contents are parsed as text
+      let fragment = Html::parse_fragment(&noscript.text().collect::<String>());
+      let img = fragment.select(selector!("img")).next()?;
+      best_image_source(img, base)
+    })
+}
+
+/// The best image of an `<img>`, from its `srcset` and `src` and the
+/// `<source>` elements of its `<picture>`. Vector images are preferred, then
+/// the highest density. Lazy-loading attributes take precedence over the
+/// placeholders they replace, with `<noscript>` images as a fallback.
+pub fn best_image_source(img: ElementRef, base: &Url) -> Option<Url> {
+  let elem = img.value();
+  let slot_width = |elem: &Element| {
+    elem
+      .attr("sizes")
+      .and_then(parse_sizes)
+      .or_else(|| elem.attr("width")?.trim().parse().ok())
+  };
+
+  let mut sources = Vec::new();
+  let mut lazy = false;
+
+  let picture = img
+    .parent()
+    .and_then(ElementRef::wrap)
+    .filter(|parent| parent.value().name() == "picture");
+
+  if let Some(picture) = picture {
+    // the dark mode variant isn't what the logo usually looks like
+    let dark_mode = regex!(r"prefers-color-scheme:\s*dark");
+
+    for source in picture.children().filter_map(ElementRef::wrap) {
+      let source = source.value();
+      if source.name() != "source" {
+        continue;
+      }
+
+      let mime_type = source
+        .attr("type")
+        .filter(|mime_type| !mime_type.trim().is_empty());
+      if let Some(mime_type) = mime_type {
+        if !IconInfo::is_supported_type(mime_type) {
+          continue;
+        }
+      }
+
+      if let Some(media) = source.attr("media") {
+        if dark_mode.is_match(&media.to_ascii_lowercase()) {
+          continue;
+        }
+      }
+
+      if let Some(srcset) = lazy_attr(source, LAZY_SRCSET_ATTRS).or_else(|| source.attr("srcset")) {
+        lazy |= source.attr("srcset") != Some(srcset);
+        let slot_width = slot_width(source).or_else(|| slot_width(elem));
+        sources.extend(
+          parse_srcset(srcset, slot_width, base)
+            .into_iter()
+            .map(|candidate| (candidate, mime_type)),
+        );
+      }
+    }
+  }
+
+  if let Some(srcset) = lazy_attr(elem, LAZY_SRCSET_ATTRS).or_else(|| elem.attr("srcset")) {
+    lazy |= elem.attr("srcset") != Some(srcset);
+    sources.extend(
+      parse_srcset(srcset, slot_width(elem), base)
+        .into_iter()
+        .map(|candidate| (candidate, None)),
+    );
+  }
+
+  let src = lazy_attr(elem, LAZY_SRC_ATTRS).or_else(|| elem.attr("src"));
+  lazy |= elem.attr("src") != src;
+
+  // a 1x1 image is a tracking pixel, or a placeholder
+  let dimension = |attr| {
+    elem
+      .attr(attr)
+      .and_then(|value| value.trim().parse::<u32>().ok())
+  };
+  let is_pixel = matches!(
+    (dimension("width"), dimension("height")),
+    (Some(0..=1), Some(0..=1))
+  );
+
+  if let Some(href) = src
+    .filter(|_| lazy || !is_pixel)
+    .and_then(|src| base.join(src).ok())
+  {
+    sources.push((
+      SrcsetCandidate {
+        href,
+        density: Some(1.0),
+        width: None,
+      },
+      None,
+    ));
+  }
+
+  // earlier sources win ties, like browsers picking the first matching <source>
+  sources
+    .into_iter()
+    .filter(|(candidate, _)| !is_placeholder(&candidate.href, lazy))
+    .enumerate()
+    .max_by(|(i, (a, a_type)), (j, (b, b_type))| {
+      let key = |candidate: &SrcsetCandidate, mime_type: Option<&str>| {
+        (
+          is_vector(&candidate.href, mime_type),
+          candidate.density.unwrap_or(1.0),
+          candidate.width.unwrap_or(0),
+        )
+      };
+
+      key(a, *a_type)
+        .partial_cmp(&key(b, *b_type))
+        .unwrap_or(Ordering::Equal)
+        .then(j.cmp(i))
+    })
+    .map(|(_, (candidate, _))| candidate.href)
+    .or_else(|| noscript_fallback(img, base))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn base() -> Url {
+    Url::parse("https://example.com/a/").unwrap()
+  }
+
+  fn candidate(href: &str, density: Option<f32>, width: Option<u32>) -> SrcsetCandidate {
+    SrcsetCandidate {
+      href: base().join(href).unwrap(),
+      density,
+      width,
+    }
+  }
+
+  fn best(html: &str) -> Option<String> {
+    let document = Html::parse_fragment(html);
+    let img = document.select(selector!("img")).next().unwrap();
+    best_image_source(img, &base()).map(String::from)
+  }
+
+  #[test]
+  fn parses_density_descriptors() {
+    assert_eq!(
+      parse_srcset(
+        " logo.png 1x,/logo@2x.png 2x ,logo@3x.png   3.5x",
+        None,
+        &base()
+      ),
+      vec![
+        candidate("logo.png", Some(1.0), None),
+        candidate("/logo@2x.png", Some(2.0), None),
+        candidate("logo@3x.png", Some(3.5), None),
+      ]
+    );
+  }
+
+  #[test]
+  fn parses_width_descriptors() {
+    let srcset = "logo-300.png 300w, logo-600.png 600w";
+
+    assert_eq!(
+      parse_srcset(srcset, Some(150.0), &base()),
+      vec![
+        candidate("logo-300.png", Some(2.0), Some(300)),
+        candidate("logo-600.png", Some(4.0), Some(600)),
+      ]
+    );
+    assert_eq!(
+      parse_srcset(srcset, None, &base()),
+      vec![
+        candidate("logo-300.png", None, Some(300)),
+        candidate("logo-600.png", None, Some(600)),
+      ]
+    );
+  }
+
+  #[test]
+  fn keeps_commas_in_urls() {
+    let data = "data:image/svg+xml,%3Csvg%3E,%3C/svg%3E";
+
+    assert_eq!(
+      parse_srcset(&format!("{} 1x,logo.png 2x", data), None, &base()),
+      vec![
+        candidate(data, Some(1.0), None),
+        candidate("logo.png", Some(2.0), None),
+      ]
+    );
+    // like browsers, only commas that end a URL separate candidates
+    assert_eq!(
+      parse_srcset("a.png,b.png 2x", None, &base()),
+      vec![candidate("a.png,b.png", Some(2.0), None)]
+    );
+    assert_eq!(
+      parse_srcset("a.png, b.png,, c.png 2x", None, &base()),
+      vec![
+        candidate("a.png", Some(1.0), None),
+        candidate("b.png", Some(1.0), None),
+        candidate("c.png", Some(2.0), None),
+      ]
+    );
+  }
+
+  #[test]
+  fn parses_sizes() {
+    assert_eq!(parse_sizes("120px"), Some(120.0));
+    assert_eq!(parse_sizes("(max-width: 600px) 80px, 120px"), Some(120.0));
+    assert_eq!(parse_sizes("(max-width: 600px) 80px"), None);
+    assert_eq!(parse_sizes("50vw"), None);
+  }
+
+  #[test]
+  fn picks_highest_density() {
+    assert_eq!(
+      best(r#"<img src="logo.png" srcset="logo@2x.png 2x, logo@3x.png 3x">"#).as_deref(),
+      Some("https://example.com/a/logo@3x.png")
+    );
+    assert_eq!(
+      best(r#"<img src="logo.png" sizes="100px" srcset="logo-100.png 100w, logo-300.png 300w">"#)
+        .as_deref(),
+      Some("https://example.com/a/logo-300.png")
+    );
+  }
+
+  #[test]
+  fn prefers_vector_sources() {
+    assert_eq!(
+      best(
+        r#"<picture>
+          <source srcset="logo.avif 2x" type="image/avif">
+          <source srcset="logo-vector" type="image/svg+xml">
+          <img src="logo.png" srcset="logo@2x.png 2x">
+        </picture>"#
+      )
+      .as_deref(),
+      Some("https://example.com/a/logo-vector")
+    );
+  }
+
+  #[test]
+  fn skips_unsupported_types_and_dark_mode() {
+    assert_eq!(
+      best(
+        r#"<picture>
+          <source srcset="logo.avif 3x" type="image/avif">
+          <source srcset="logo-dark.svg" media="(prefers-color-scheme: dark)">
+          <source srcset="logo@2x.webp 2x" type="image/webp">
+          <img src="logo.png">
+        </picture>"#
+      )
+      .as_deref(),
+      Some("https://example.com/a/logo.png")
+    );
+    assert_eq!(
+      best(
+        r#"<picture>
+          <source srcset="logo@2x.png 2x" media="(min-width: 600px)">
+          <img src="logo.png">
+        </picture>"#
+      )
+      .as_deref(),
+      Some("https://example.com/a/logo@2x.png")
+    );
+  }
+
+  #[test]
+  fn resolves_lazy_attributes() {
+    assert_eq!(
+      best(r#"<img src="data:image/gif;base64,R0lGODlhAQABAAAAACw=" data-src="logo.png">"#)
+        .as_deref(),
+      Some("https://example.com/a/logo.png")
+    );
+    assert_eq!(
+      best(r#"<img src="placeholder.png" data-lazy-src="logo.png" data-srcset="logo@2x.png 2x">"#)
+        .as_deref(),
+      Some("https://example.com/a/logo@2x.png")
+    );
+    assert_eq!(
+      best(r#"<img src="logo.png" data-src=" ">"#).as_deref(),
+      Some("https://example.com/a/logo.png")
+    );
+  }
+
+  #[test]
+  fn skips_placeholders() {
+    for src in [
+      "spacer.gif",
+      "/img/blank_1x1.png",
+      "lazy-load.svg",
+      "Placeholder.JPG",
+      "data:image/gif;base64,R0lGODlhAQABAAAAACw=",
+    ] {
+      assert_eq!(best(&format!(r#"<img src="{}">"#, src)), None, "{}", src);
+    }
+
+    // tracking pixels
+    assert_eq!(best(r#"<img src="logo.png" width="1" height="1">"#), None);
+
+    // blurred previews are only placeholders with lazy-loading
+    let preview = format!("data:image/jpeg;base64,{}", "A".repeat(300));
+    assert_eq!(
+      best(&format!(r#"<img src="{}">"#, preview)).as_deref(),
+      Some(preview.as_str())
+    );
+    assert_eq!(
+      best(&format!(r#"<img src="{}" data-src="logo.png">"#, preview)).as_deref(),
+      Some("https://example.com/a/logo.png")
+    );
+    assert_eq!(
+      best(r#"<img src="blankets.png">"#).as_deref(),
+      Some("https://example.com/a/blankets.png")
+    );
+  }
+
+  #[test]
+  fn falls_back_to_noscript_images() {
+    let document = Html::parse_document(
+      r#"<div class="logo">
+        <img class="lazyload" src="spacer.gif">
+        <noscript><img src="logo.png" srcset="logo@2x.png 2x"></noscript>
+      </div>
+      <div><picture><img src="pixel.gif"></picture><noscript><img src="pic.png"></noscript></div>"#,
+    );
+    let mut imgs = document.select(selector!("div > img, picture > img"));
+
+    assert_eq!(
+      best_image_source(imgs.next().unwrap(), &base()).map(String::from),
+      Some("https://example.com/a/logo@2x.png".to_string())
+    );
+    assert_eq!(
+      best_image_source(imgs.next().unwrap(), &base()).map(String::from),
+      Some("https://example.com/a/pic.png".to_string())
+    );
+  }
+}
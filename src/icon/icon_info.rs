@@ -13,6 +13,7 @@ use std::{
   io,
 };
 
+#[allow(clippy::upper_case_acronyms)]
 enum IconKind {
   SVG,
   PNG,
@@ -69,6 +70,11 @@ impl IconInfo {
     }
   }
 
+  /// Reads the size of an SVG that was already downloaded
+  pub(crate) async fn from_svg(svg: &[u8]) -> Result<IconInfo, Box<dyn Error>> {
+    IconInfo::decode(&mut Cursor::new(svg), Some(IconKind::SVG)).await
+  }
+
   pub async fn load(
     url: Url,
     headers: HeaderMap,
@@ -114,9 +120,7 @@ impl IconInfo {
 
         let body = res
           .bytes_stream()
-          .map(|result| {
-            result.map_err(|error| io::Error::new(io::ErrorKind::Other, error.to_string()))
-          })
+          .map(|result| result.map_err(|error| io::Error::other(error.to_string())))
           .into_async_read();
 
         (mime, Box::new(body))
@@ -176,6 +180,25 @@ impl IconInfo {
     IconInfo::decode(&mut body, kind).await
   }
 
+  /// Whether icons with this (declared) mime type can be decoded, so
+  /// unsupported ones can be skipped without fetching them.
+  pub fn is_supported_type(mime_type: &str) -> bool {
+    let mime = match mime_type.parse::<MediaType>() {
+      Ok(mime) => mime,
+      Err(_) => return true,
+    };
+
+    matches!(
+      (mime.type_(), mime.subtype()),
+      (mime::IMAGE, mime::PNG)
+        | (mime::IMAGE, mime::JPEG)
+        | (mime::IMAGE, "x-icon")
+        | (mime::IMAGE, "vnd.microsoft.icon")
+        | (mime::IMAGE, mime::GIF)
+        | (mime::IMAGE, mime::SVG)
+    )
+  }
+
   pub fn size(&self) -> Option<&IconSize> {
     match self {
       IconInfo::ICO { sizes } => Some(sizes.largest()),
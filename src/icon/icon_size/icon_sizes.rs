@@ -32,6 +32,14 @@ impl IconSizes {
   pub fn largest(&self) -> &IconSize {
     self.0.first()
   }
+
+  /// Whether a `sizes` attribute contains the `any` keyword, which declares a
+  /// scalable icon (e.g. an SVG) that isn't parsed as an [`IconSize`]
+  pub fn contains_any(sizes_str: &str) -> bool {
+    sizes_str
+      .split_ascii_whitespace()
+      .any(|size| size.eq_ignore_ascii_case("any"))
+  }
 }
 
 impl TryFrom<&str> for IconSizes {
@@ -85,7 +93,7 @@ impl IntoIterator for IconSizes {
 
 impl Ord for IconSizes {
   fn cmp(&self, other: &Self) -> Ordering {
-    self.largest().cmp(&other.largest())
+    self.largest().cmp(other.largest())
   }
 }
 
@@ -112,3 +120,20 @@ impl From<IconSize> for IconSizes {
     IconSizes(vec1![size])
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn keeps_any_keyword() {
+    assert!(IconSizes::contains_any("any"));
+    assert!(IconSizes::contains_any("16x16  ANY"));
+    assert!(!IconSizes::contains_any("16x16 32x32"));
+    assert!(!IconSizes::contains_any("anyway"));
+
+    let sizes = IconSizes::try_from("any 32x32 16x16").unwrap();
+    assert_eq!(sizes.to_string(), "32x32 16x16");
+    assert!(IconSizes::try_from("any").is_err());
+  }
+}
@@ -18,9 +18,14 @@ use std::{
 };
 use url::Url;
 
-#[derive(Debug, Clone, PartialOrd, PartialEq, Ord, Eq, SerializeDisplay, DeserializeFromStr)]
+#[derive(
+  Debug, Clone, PartialOrd, PartialEq, Ord, Eq, Hash, SerializeDisplay, DeserializeFromStr,
+)]
 pub enum IconKind {
   AppIcon,
+  AppShortcutIcon,
+  /// An `apple-touch-startup-image` splash screen, see [`Icon::media`]
+  AppStartupImage,
   SiteFavicon,
   SiteLogo,
 }
@@ -30,6 +35,8 @@ impl Display for IconKind {
     f.write_str(match self {
       IconKind::SiteLogo => "site_logo",
       IconKind::AppIcon => "app_icon",
+      IconKind::AppShortcutIcon => "app_shortcut_icon",
+      IconKind::AppStartupImage => "app_startup_image",
       IconKind::SiteFavicon => "site_favicon",
     })
   }
@@ -42,17 +49,101 @@ impl FromStr for IconKind {
     match kind {
       "site_logo" => Ok(IconKind::SiteLogo),
       "app_icon" => Ok(IconKind::AppIcon),
+      "app_shortcut_icon" => Ok(IconKind::AppShortcutIcon),
+      "app_startup_image" => Ok(IconKind::AppStartupImage),
       "site_favicon" => Ok(IconKind::SiteFavicon),
       _ => Err("unknown icon kind!".into()),
     }
   }
 }
 
+/// The [`purpose`](https://developer.mozilla.org/en-US/docs/Web/Manifest/icons#values)
+/// of a web app manifest icon
+#[derive(
+  Debug, Clone, Copy, PartialOrd, PartialEq, Ord, Eq, Hash, SerializeDisplay, DeserializeFromStr,
+)]
+pub enum IconPurpose {
+  Any,
+  Maskable,
+  Monochrome,
+}
+
+impl IconPurpose {
+  /// Parses a space separated `purpose` list, ignoring unknown values
+  pub fn parse_list(purpose: &str) -> Vec<IconPurpose> {
+    purpose
+      .split_ascii_whitespace()
+      .filter_map(|purpose| purpose.to_ascii_lowercase().parse().ok())
+      .unique()
+      .collect()
+  }
+}
+
+impl Display for IconPurpose {
+  fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+    f.write_str(match self {
+      IconPurpose::Any => "any",
+      IconPurpose::Maskable => "maskable",
+      IconPurpose::Monochrome => "monochrome",
+    })
+  }
+}
+
+impl FromStr for IconPurpose {
+  type Err = String;
+
+  fn from_str(purpose: &str) -> Result<Self, Self::Err> {
+    match purpose {
+      "any" => Ok(IconPurpose::Any),
+      "maskable" => Ok(IconPurpose::Maskable),
+      "monochrome" => Ok(IconPurpose::Monochrome),
+      _ => Err("unknown icon purpose!".into()),
+    }
+  }
+}
+
+/// Where an icon was discovered
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IconSource {
+  /// A `<link>` tag in the document head
+  HeadTag,
+  /// A `<link>` tag found after the head was closed
+  BodyTag,
+  /// A `Link` response header of the page
+  LinkHeader,
+  /// The web app manifest at the given URL
+  Manifest(Url),
+  /// The RSS or Atom feed at the given URL
+  Feed(Url),
+  /// The OpenSearch description at the given URL
+  OpenSearch(Url),
+  /// A well-known path, see [`Probe`](crate::Probe)
+  Probe,
+  /// An element on the page, picked by the site logo heuristics
+  PageContent,
+  /// The BIMI DNS record of an email domain
+  Bimi,
+  /// The store listing of one of the site's native apps
+  AppStore,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub struct Icon {
   pub url: Url,
   pub headers: HashMap<String, String>,
   pub kind: IconKind,
+  /// The manifest `purpose` of the icon, empty when it wasn't declared
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  pub purpose: Vec<IconPurpose>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub source: Option<IconSource>,
+  /// The declared `media` query, e.g. the devices a startup image is for
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub media: Option<String>,
+  /// Whether the icon was declared with `sizes="any"`
+  #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+  pub scalable: bool,
   #[serde(flatten)]
   pub info: IconInfo,
 }
@@ -86,10 +177,24 @@ impl Icon {
       url,
       headers,
       kind,
+      purpose: Vec::new(),
+      source: None,
+      media: None,
+      scalable: false,
       info,
     }
   }
 
+  /// Whether the icon can be used for the given purpose. Icons without a
+  /// declared purpose are treated as `any`.
+  pub fn has_purpose(&self, purpose: IconPurpose) -> bool {
+    if self.purpose.is_empty() {
+      purpose == IconPurpose::Any
+    } else {
+      self.purpose.contains(&purpose)
+    }
+  }
+
   pub async fn load(
     url: Url,
     kind: IconKind,
@@ -104,15 +209,23 @@ impl Icon {
     kind: IconKind,
     sizes: Option<String>,
   ) -> Result<Self, Box<dyn Error>> {
+    let scalable = sizes.as_deref().is_some_and(IconSizes::contains_any);
     let info = IconInfo::load(url.clone(), (&headers).try_into().unwrap(), sizes).await?;
 
-    Ok(Icon::new_with_headers(url, headers, kind, info))
+    let mut icon = Icon::new_with_headers(url, headers, kind, info);
+    icon.scalable = scalable;
+
+    Ok(icon)
   }
 }
 
 impl Ord for Icon {
+  // startup images are splash screens, they come after the actual icons
   fn cmp(&self, other: &Self) -> Ordering {
-    self.info.cmp(&other.info)
+    (self.kind == IconKind::AppStartupImage)
+      .cmp(&(other.kind == IconKind::AppStartupImage))
+      .then_with(|| self.info.cmp(&other.info))
+      .then_with(|| other.scalable.cmp(&self.scalable))
   }
 }
 
@@ -121,3 +234,26 @@ impl PartialOrd for Icon {
     Some(self.cmp(other))
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const SVG: &str =
+    "data:image/svg+xml,%3Csvg%20xmlns='http://www.w3.org/2000/svg'%20width='10'%20height='10'/%3E";
+
+  #[tokio::test]
+  async fn keeps_scalable_declaration() {
+    let url = Url::parse(SVG).unwrap();
+
+    let scalable = Icon::load(url.clone(), IconKind::SiteFavicon, Some("any".to_string()))
+      .await
+      .unwrap();
+    assert!(scalable.scalable);
+    assert_eq!(scalable.info.size(), Some(&IconSize::new(10, 10)));
+
+    let icon = Icon::load(url, IconKind::SiteFavicon, None).await.unwrap();
+    assert!(!icon.scalable);
+    assert!(scalable < icon);
+  }
+}
@@ -1,38 +1,170 @@
-use crate::{html_parser, utils::push_url, Icon, IconKind, CLIENT};
-use flo_stream::{MessagePublisher, Publisher, StreamPublisher};
+use crate::{
+  html_parser::{self, HeadTags},
+  manifest::LoadedManifest,
+  AppStoreResolver, Diagnostic, DnsResolver, DohResolver, Icon, IconCandidate, IconKind,
+  IconSource, LogoHeuristics, NativeApp, Probe, ProbeKind, ScoredLogo, SiteMetadata, CLIENT,
+};
 use futures::future::{join_all, select_all};
 use futures::prelude::*;
-use futures::{join, StreamExt};
+use futures::{channel::oneshot, join, StreamExt};
 use itertools::Itertools;
-use reqwest::{header::*, IntoUrl};
+use reqwest::{header::*, IntoUrl, Response};
+use scraper::Html;
+use std::cell::RefCell;
 use std::convert::TryInto;
 use std::error::Error;
+use std::rc::Rc;
 use url::Url;
 use vec1::Vec1;
 
+type Blacklist = Box<dyn Fn(&Url) -> bool>;
+/// The manifest and icon URLs to probe in a directory
+type ProbeUrls = (Vec<Url>, Vec<(IconKind, Url)>);
+
 pub struct SiteIcons {
-  blacklist: Option<Box<dyn Fn(&Url) -> bool>>,
+  blacklist: Option<Blacklist>,
+  max_client_redirects: usize,
+  probes: Vec<Probe>,
+  probe_depth: usize,
+  body_scan_bytes: usize,
+  stylesheet_bytes: usize,
+  startup_images: bool,
+  pub(crate) dns_resolver: Box<dyn DnsResolver>,
+  pub(crate) app_store_resolver: Option<Box<dyn AppStoreResolver>>,
+  logo_heuristics: LogoHeuristics,
+}
+
+#[derive(Debug, Clone)]
+pub struct WebsiteInfo {
+  /// The final page URL, after HTTP and client-side redirects
+  pub url: Url,
+  /// Icons sorted from highest to lowest resolution, followed by the startup images
+  pub icons: Vec<Icon>,
+  pub metadata: SiteMetadata,
+  /// The site's native apps, from smart app banner tags and manifests
+  pub apps: Vec<NativeApp>,
+  /// Problems that were worked around, such as repaired manifests
+  pub diagnostics: Vec<Diagnostic>,
+}
+
+/// The icons declared by a site, see [`SiteIcons::discover`]
+#[derive(Debug, Clone)]
+pub struct DiscoveredSite {
+  /// The final page URL, after HTTP and client-side redirects
+  pub url: Url,
+  /// Declared icons, followed by the unverified well-known paths and the
+  /// most likely site logo
+  pub candidates: Vec<IconCandidate>,
+  /// Every site logo candidate on the page, from most to least likely
+  pub logos: Vec<ScoredLogo>,
+  pub metadata: SiteMetadata,
+  pub apps: Vec<NativeApp>,
+  pub diagnostics: Vec<Diagnostic>,
 }
 
 #[derive(Debug, Clone)]
 enum LoadedKind {
-  DefaultManifest(Option<Vec1<Icon>>),
-  HeadTags(Option<Vec1<Icon>>),
-  DefaultFavicon(Option<Icon>),
+  DefaultManifest(LoadedManifest),
+  HeadTags(HeadTags),
+  DefaultIcons(Option<Vec1<Icon>>),
   SiteLogo(Option<Icon>),
 }
 
+impl Default for SiteIcons {
+  fn default() -> Self {
+    SiteIcons::new()
+  }
+}
+
 impl SiteIcons {
   pub fn new() -> Self {
-    SiteIcons { blacklist: None }
+    SiteIcons {
+      blacklist: None,
+      max_client_redirects: 0,
+      probes: Probe::defaults(),
+      probe_depth: 1,
+      body_scan_bytes: 0,
+      stylesheet_bytes: 0,
+      startup_images: false,
+      dns_resolver: Box::new(DohResolver::default()),
+      app_store_resolver: None,
+      logo_heuristics: LogoHeuristics::default(),
+    }
   }
 
   pub fn new_with_blacklist(blacklist: impl Fn(&Url) -> bool + 'static) -> Self {
     SiteIcons {
       blacklist: Some(Box::new(blacklist)),
+      ..SiteIcons::new()
     }
   }
 
+  /// Follow up to `max_redirects` meta-refresh / `window.location` redirects
+  /// on landing pages before parsing them. Disabled by default, as the page
+  /// then has to be downloaded completely before it can be parsed.
+  pub fn with_client_redirects(mut self, max_redirects: usize) -> Self {
+    self.max_client_redirects = max_redirects;
+    self
+  }
+
+  /// Replace the well-known paths that are probed for icons and manifests
+  /// (see [`Probe::defaults`])
+  pub fn with_probes(mut self, probes: Vec<Probe>) -> Self {
+    self.probes = probes;
+    self
+  }
+
+  /// Probe at most `max_depth` directories below the root, for sites hosted
+  /// under a subpath. The directories are tried from the deepest one up, until
+  /// one of them has results. Defaults to 1, e.g. `/app/` and `/` for `/app/page`.
+  pub fn with_probe_depth(mut self, max_depth: usize) -> Self {
+    self.probe_depth = max_depth;
+    self
+  }
+
+  /// Keep scanning up to `max_bytes` of the body for `<link rel=icon>` and
+  /// `<link rel=manifest>` tags that were emitted outside the head. Disabled by default.
+  pub fn with_body_scan(mut self, max_bytes: usize) -> Self {
+    self.body_scan_bytes = max_bytes;
+    self
+  }
+
+  /// Download up to `max_bytes` of the page's linked stylesheets, to find
+  /// logos set as a CSS `background-image`. Inline styles and `<style>`
+  /// blocks are always checked. Disabled by default.
+  pub fn with_stylesheets(mut self, max_bytes: usize) -> Self {
+    self.stylesheet_bytes = max_bytes;
+    self
+  }
+
+  /// Also download the `apple-touch-startup-image` splash screens. Disabled by
+  /// default, as sites often declare one for every device size.
+  pub fn with_startup_images(mut self) -> Self {
+    self.startup_images = true;
+    self
+  }
+
+  /// Replace the resolver used for DNS lookups, e.g. by [`SiteIcons::load_bimi`].
+  /// Defaults to DNS-over-HTTPS through Cloudflare.
+  pub fn with_dns_resolver(mut self, resolver: impl DnsResolver + 'static) -> Self {
+    self.dns_resolver = Box::new(resolver);
+    self
+  }
+
+  /// Replace the selectors, weights and skip patterns used to pick the site
+  /// logo from the page (see [`LogoHeuristics::default`])
+  pub fn with_logo_heuristics(mut self, heuristics: LogoHeuristics) -> Self {
+    self.logo_heuristics = heuristics;
+    self
+  }
+
+  /// Load the store icons of the site's native apps (see [`WebsiteInfo::apps`])
+  /// through `resolver`, e.g. [`ItunesResolver`]. Disabled by default.
+  pub fn with_app_store_resolver(mut self, resolver: impl AppStoreResolver + 'static) -> Self {
+    self.app_store_resolver = Some(Box::new(resolver));
+    self
+  }
+
   pub fn is_blacklisted(&self, url: &Url) -> bool {
     if let Some(is_blacklisted) = &self.blacklist {
       is_blacklisted(url)
@@ -41,147 +173,201 @@ impl SiteIcons {
     }
   }
 
+  /// The well-known manifest and icon URLs to probe in each directory,
+  /// deepest directory first
+  fn probe_urls(&self, url: &Url) -> Vec<ProbeUrls> {
+    Probe::dirs(url, self.probe_depth)
+      .iter()
+      .map(|dir| {
+        let mut manifest_urls = Vec::new();
+        let mut icon_urls = Vec::new();
+
+        for probe in &self.probes {
+          if let Some(url) = probe.url(dir) {
+            match &probe.kind {
+              ProbeKind::Manifest => manifest_urls.push(url),
+              ProbeKind::Icon(kind) => icon_urls.push((kind.clone(), url)),
+            }
+          }
+        }
+
+        (manifest_urls, icon_urls)
+      })
+      .collect()
+  }
+
   pub async fn load_website<U: IntoUrl>(
     &mut self,
     url: U,
     best_matches_only: bool,
   ) -> Result<Vec<Icon>, Box<dyn Error>> {
+    Ok(self.load_website_info(url, best_matches_only).await?.icons)
+  }
+
+  /// Like [`SiteIcons::load_website`], but also returns the site metadata
+  /// declared in its web app manifest
+  pub async fn load_website_info<U: IntoUrl>(
+    &mut self,
+    url: U,
+    best_matches_only: bool,
+  ) -> Result<WebsiteInfo, Box<dyn Error>> {
     let url = url.into_url()?;
 
-    let manifest_urls = vec![
-      push_url(&url, "manifest.json"),
-      push_url(&url, "manifest.webmanifest"),
-      url.join("/manifest.json")?,
-      url.join("/manifest.webmanifest")?,
-    ]
-    .into_iter()
-    .unique();
-
-    let favicon_urls = vec![
-      push_url(&url, "favicon.svg"),
-      url.join("/favicon.svg")?,
-      push_url(&url, "favicon.ico"),
-      url.join("/favicon.ico")?,
-    ]
-    .into_iter()
-    .unique();
+    let probe_urls = self.probe_urls(&url);
 
     let html_response = async {
-      let res = CLIENT
-        .get(url.clone())
-        .header(ACCEPT, "text/html")
-        .send()
-        .await
-        .ok()?
-        .error_for_status()
-        .ok()?;
+      let res = fetch_html(url.clone()).await?;
 
-      let url = res.url().clone();
+      let (url, links, body) = if self.max_client_redirects > 0 {
+        let (url, headers, body) = self.follow_client_redirects(res).await?;
 
-      if self.is_blacklisted(&url) {
-        None
+        (
+          url,
+          html_parser::parse_link_headers(&headers),
+          stream::once(future::ready(Ok(body))).boxed_local(),
+        )
       } else {
+        let url = res.url().clone();
+        let links = html_parser::parse_link_headers(res.headers());
         let body = res.bytes_stream().map(|res| {
           res
             .map(|bytes| bytes.to_vec())
             .map_err(|err| err.to_string())
         });
 
-        let mut publisher = Publisher::new(128);
-        let subscriber = publisher.subscribe();
+        (url, links, body.boxed_local())
+      };
 
-        Some((
-          url,
-          async move { StreamPublisher::new(&mut publisher, body).await }.shared(),
-          subscriber,
-        ))
+      if self.is_blacklisted(&url) {
+        None
+      } else {
+        Some((url, links, Rc::new(RefCell::new(Some(body)))))
       }
     }
     .shared();
 
+    // the page is parsed once, and its head tags are sent over as soon as they're known
+    let (head_tx, head_rx) = oneshot::channel();
+
     let mut futures = vec![
       async {
-        let html_response = html_response.clone().await;
+        let head_tags = match html_response.clone().await {
+          Some((url, links, _)) => {
+            html_parser::parse_head(&url, links, head_rx.map(Result::ok), self.startup_images).await
+          }
+          None => Default::default(),
+        };
 
-        LoadedKind::HeadTags(match html_response {
-          Some((url, _, body)) => html_parser::parse_head(&url, body)
-            .await
-            .ok()
-            .and_then(|icons| icons.try_into().ok()),
-          None => None,
-        })
+        LoadedKind::HeadTags(head_tags)
       }
       .boxed_local(),
       async {
-        let html_response = html_response.clone().await;
+        let html_response = html_response
+          .clone()
+          .await
+          .and_then(|(url, _, body)| Some((url, body.take()?)));
 
         LoadedKind::SiteLogo(match html_response {
-          Some((url, complete, body)) => {
-            let (icons, _) = join!(
-              html_parser::parse_site_logo(&url, body, |url| self.is_blacklisted(url)),
-              complete
-            );
-
-            icons.ok()
-          }
+          Some((url, body)) => html_parser::parse_page(
+            &url,
+            body,
+            head_tx,
+            self.body_scan_bytes,
+            &self.logo_heuristics,
+            self.stylesheet_bytes,
+            |url| self.is_blacklisted(url),
+          )
+          .await
+          .ok(),
           None => None,
         })
       }
       .boxed_local(),
       async {
-        let manifests = join_all(manifest_urls.map(|url| SiteIcons::load_manifest(url))).await;
+        let manifests = probe_deepest(probe_urls.iter().map(|(manifest_urls, _)| {
+          manifest_urls
+            .iter()
+            .cloned()
+            .map(SiteIcons::load_manifest_with_metadata)
+            .collect()
+        }))
+        .await;
 
-        LoadedKind::DefaultManifest(
-          manifests
-            .into_iter()
-            .find_map(|manifest| manifest.ok().and_then(|icons| icons.try_into().ok())),
-        )
+        LoadedKind::DefaultManifest(LoadedManifest::merge(manifests))
       }
       .boxed_local(),
       async {
-        let favicons =
-          join_all(favicon_urls.map(|url| Icon::load(url.clone(), IconKind::SiteFavicon, None)))
-            .await;
+        let icons = probe_deepest(probe_urls.iter().map(|(_, icon_urls)| {
+          icon_urls
+            .iter()
+            .cloned()
+            .map(|(kind, url)| async move {
+              let mut icon = Icon::load(url, kind, None).await?;
+              icon.source = Some(IconSource::Probe);
+              Ok::<_, Box<dyn Error>>(icon)
+            })
+            .collect()
+        }))
+        .await;
 
-        LoadedKind::DefaultFavicon(favicons.into_iter().find_map(|favicon| favicon.ok()))
+        LoadedKind::DefaultIcons(icons.try_into().ok())
       }
       .boxed_local(),
     ];
 
     let mut icons: Vec<Icon> = Vec::new();
+    // metadata from declared manifests takes precedence over probed ones
+    let mut metadata = SiteMetadata::default();
+    let mut probed_metadata = SiteMetadata::default();
+    let mut apps = Vec::new();
+    let mut probed_apps = Vec::new();
+    let mut diagnostics = Vec::new();
     let mut found_best_match = false;
     let mut previous_loads = Vec::new();
 
     while !futures.is_empty() {
       let (loaded, index, _) = select_all(&mut futures).await;
-      futures.remove(index);
+      drop(futures.remove(index));
 
       match loaded.clone() {
-        LoadedKind::DefaultManifest(manifest_icons) => {
-          if let Some(manifest_icons) = manifest_icons {
-            icons.extend(manifest_icons);
+        LoadedKind::DefaultManifest(manifest) => {
+          probed_metadata = manifest.metadata;
+          probed_apps = manifest.apps;
+          diagnostics.extend(manifest.diagnostics);
+
+          if !manifest.icons.is_empty() {
+            icons.extend(manifest.icons);
             found_best_match = true;
           }
         }
-        LoadedKind::DefaultFavicon(favicon) => {
-          if let Some(favicon) = favicon {
-            icons.push(favicon);
+        LoadedKind::DefaultIcons(default_icons) => {
+          if let Some(default_icons) = default_icons {
+            icons.extend(default_icons);
 
             if previous_loads
               .iter()
-              .any(|kind| matches!(kind, LoadedKind::HeadTags(_)))
+              .any(|kind| matches!(kind, LoadedKind::HeadTags(..)))
             {
               found_best_match = true;
             }
           }
         }
-        LoadedKind::HeadTags(head_icons) => {
-          if let Some(head_icons) = head_icons {
-            icons.extend(head_icons);
-            found_best_match = true;
-          } else if previous_loads
+        LoadedKind::HeadTags(head_tags) => {
+          metadata.merge(&head_tags.metadata);
+          apps.extend(head_tags.apps);
+          diagnostics.extend(head_tags.diagnostics);
+
+          // startup images are splash screens, not a replacement for icons
+          let has_icons = head_tags
+            .icons
             .iter()
-            .any(|kind| matches!(kind, LoadedKind::DefaultFavicon(Some(_))))
+            .any(|icon| icon.kind != IconKind::AppStartupImage);
+          icons.extend(head_tags.icons);
+
+          if has_icons
+            || previous_loads
+              .iter()
+              .any(|kind| matches!(kind, LoadedKind::DefaultIcons(Some(_))))
           {
             found_best_match = true;
           }
@@ -196,13 +382,245 @@ impl SiteIcons {
       previous_loads.push(loaded);
 
       icons.sort();
-      icons = icons.into_iter().unique().collect();
+      icons = icons
+        .into_iter()
+        .unique_by(|icon| (icon.url.clone(), icon.kind.clone()))
+        .collect();
 
       if best_matches_only && found_best_match {
         break;
       }
     }
 
-    Ok(icons)
+    let url = match html_response.peek() {
+      Some(Some((url, ..))) => url.clone(),
+      _ => url.clone(),
+    };
+
+    metadata.merge(&probed_metadata);
+
+    let apps = apps
+      .into_iter()
+      .chain(probed_apps)
+      // the same app is often declared by both a meta tag and the manifest
+      .unique_by(NativeApp::key)
+      .collect::<Vec<_>>();
+
+    // store icons are only looked up when an `AppStoreResolver` is configured
+    let app_icons = self.app_icon_candidates(&apps).await;
+    if !app_icons.is_empty() {
+      icons.extend(SiteIcons::resolve(app_icons).await);
+      icons.sort();
+    }
+
+    Ok(WebsiteInfo {
+      url,
+      icons,
+      metadata,
+      apps,
+      diagnostics: diagnostics.into_iter().unique().collect(),
+    })
   }
+
+  /// Finds the icons a site declares, along with its manifests, without
+  /// downloading any of the images. Only the page and manifests are fetched,
+  /// the candidates can be loaded later with [`SiteIcons::resolve`].
+  pub async fn discover<U: IntoUrl>(&self, url: U) -> Result<DiscoveredSite, Box<dyn Error>> {
+    let url = url.into_url()?;
+    let probe_urls = self.probe_urls(&url);
+
+    let page = async {
+      let res = fetch_html(url.clone()).await?;
+      self.follow_client_redirects(res).await
+    };
+
+    let (page, probed_manifests) = join!(
+      page,
+      probe_deepest(probe_urls.iter().map(|(manifest_urls, _)| {
+        manifest_urls
+          .iter()
+          .cloned()
+          .map(SiteIcons::discover_manifest)
+          .collect()
+      }))
+    );
+
+    let mut candidates = Vec::new();
+    let mut manifest_urls = Vec::new();
+    let mut feed_urls = Vec::new();
+    let mut apps = Vec::new();
+    let mut logos = Vec::new();
+
+    let url = match page {
+      Some((url, _, _)) if self.is_blacklisted(&url) => url,
+      Some((url, headers, body)) => {
+        let document = Html::parse_document(&String::from_utf8_lossy(&body));
+
+        let links = html_parser::parse_link_headers(&headers)
+          .into_iter()
+          .chain(html_parser::parse_head_links(&document))
+          .filter(|link| self.body_scan_bytes > 0 || link.source != IconSource::BodyTag)
+          .collect::<Vec<_>>();
+
+        candidates.extend(links.iter().filter_map(|link| link.icon_candidate(&url)));
+        manifest_urls.extend(links.iter().filter_map(|link| link.manifest_url(&url)));
+        apps.extend(html_parser::parse_native_apps(&document));
+        // only the first feed and OpenSearch description, like `parse_head`
+        feed_urls.extend(
+          links
+            .iter()
+            .filter_map(|link| link.feed_url(&url))
+            .unique_by(|(_, kind)| *kind),
+        );
+        let urls = html_parser::stylesheet_urls(&document, &url)
+          .into_iter()
+          .filter(|url| !self.is_blacklisted(url))
+          .collect();
+        let stylesheets = html_parser::load_style_rules(urls, self.stylesheet_bytes).await;
+
+        logos = html_parser::score_site_logos(
+          &document,
+          &url,
+          &self.logo_heuristics,
+          &stylesheets,
+          |url| self.is_blacklisted(url),
+        );
+
+        url
+      }
+      None => url,
+    };
+
+    let (manifests, feeds) = join!(
+      join_all(
+        manifest_urls
+          .into_iter()
+          .unique()
+          .map(SiteIcons::discover_manifest),
+      ),
+      join_all(
+        feed_urls
+          .into_iter()
+          .map(|(url, kind)| SiteIcons::discover_feed(url, kind)),
+      )
+    );
+
+    let mut metadata = SiteMetadata::default();
+    let mut diagnostics = Vec::new();
+
+    // declared manifests take precedence over probed ones
+    for manifest in manifests.into_iter().flatten().chain(probed_manifests) {
+      candidates.extend(manifest.candidates);
+      metadata.merge(&manifest.metadata);
+      apps.extend(manifest.apps);
+      diagnostics.extend(manifest.diagnostics);
+    }
+
+    candidates.extend(feeds.into_iter().flatten().flatten());
+
+    let apps = apps
+      .into_iter()
+      .unique_by(NativeApp::key)
+      .collect::<Vec<_>>();
+    candidates.extend(self.app_icon_candidates(&apps).await);
+
+    for (kind, href) in probe_urls.into_iter().flat_map(|(_, icon_urls)| icon_urls) {
+      candidates.push(IconCandidate {
+        href,
+        kind: kind.clone(),
+        rel: None,
+        sizes: None,
+        mime_type: None,
+        media: None,
+        purpose: Vec::new(),
+        source: IconSource::Probe,
+      });
+    }
+
+    candidates.extend(logos.first().map(ScoredLogo::to_candidate));
+
+    Ok(DiscoveredSite {
+      url,
+      candidates: candidates
+        .into_iter()
+        .filter(|candidate| !self.is_blacklisted(&candidate.href))
+        .unique_by(|candidate| (candidate.href.clone(), candidate.kind.clone()))
+        .collect(),
+      logos,
+      metadata,
+      apps,
+      diagnostics: diagnostics.into_iter().unique().collect(),
+    })
+  }
+
+  /// Loads discovered candidates, skipping the ones that fail to load.
+  /// Icons are sorted from highest to lowest resolution, startup images last.
+  pub async fn resolve(candidates: Vec<IconCandidate>) -> Vec<Icon> {
+    join_all(candidates.into_iter().map(IconCandidate::resolve))
+      .await
+      .into_iter()
+      .flatten()
+      .sorted()
+      .collect()
+  }
+
+  /// Downloads the page, following client-side redirects until a page
+  /// that isn't a redirect is reached
+  async fn follow_client_redirects(&self, mut res: Response) -> Option<(Url, HeaderMap, Vec<u8>)> {
+    let mut visited = vec![res.url().clone()];
+
+    loop {
+      let url = res.url().clone();
+      let headers = res.headers().clone();
+      let body = res.bytes().await.ok()?.to_vec();
+
+      if visited.len() > self.max_client_redirects {
+        return Some((url, headers, body));
+      }
+
+      let target = match html_parser::parse_client_redirect(&url, &String::from_utf8_lossy(&body)) {
+        Some(target) if !visited.contains(&target) && !self.is_blacklisted(&target) => target,
+        _ => return Some((url, headers, body)),
+      };
+
+      match fetch_html(target).await {
+        Some(next) => {
+          visited.push(next.url().clone());
+          res = next;
+        }
+        None => return Some((url, headers, body)),
+      }
+    }
+  }
+}
+
+async fn fetch_html(url: Url) -> Option<Response> {
+  CLIENT
+    .get(url)
+    .header(ACCEPT, "text/html")
+    .send()
+    .await
+    .ok()?
+    .error_for_status()
+    .ok()
+}
+
+/// Runs the probes of each directory in turn, deepest first, until one of
+/// the directories has probes that succeed
+async fn probe_deepest<T, E>(
+  dirs: impl IntoIterator<Item = Vec<impl Future<Output = Result<T, E>>>>,
+) -> Vec<T> {
+  for probes in dirs {
+    let found = join_all(probes)
+      .await
+      .into_iter()
+      .flatten()
+      .collect::<Vec<_>>();
+
+    if !found.is_empty() {
+      return found;
+    }
+  }
+
+  Vec::new()
 }
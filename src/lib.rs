@@ -25,13 +25,30 @@ extern crate log;
 
 #[macro_use]
 mod utils;
+mod bimi;
+mod candidate;
+mod diagnostic;
+mod feed;
 mod html_parser;
 mod icon;
 mod icons;
+mod logo_heuristics;
 mod manifest;
+mod native_app;
+mod probe;
 
+pub use bimi::*;
+pub use candidate::*;
+pub use diagnostic::*;
 pub use icon::*;
 pub use icons::*;
+pub use logo_heuristics::*;
+pub use manifest::{
+  Manifest, ManifestIcon, ManifestRelatedApplication, ManifestRepair, ManifestShortcut,
+  SiteMetadata,
+};
+pub use native_app::*;
+pub use probe::*;
 
 use once_cell::sync::Lazy;
 use reqwest::{
@@ -0,0 +1,247 @@
+use crate::{IconCandidate, IconKind, IconSource};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+/// When a [`LogoRule`] applies to a candidate element
+#[derive(Debug, Clone)]
+pub enum LogoCondition {
+  /// The element is inside an element matching the CSS selector,
+  /// e.g. `header`, `nav` or `[role=banner]`
+  Inside(String),
+  /// The element is the first one matched on the page
+  FirstMatch,
+  /// The element, or one of its ancestors, has one of the attributes with a
+  /// (lowercased) value matching the pattern
+  AttributeMatches { attrs: Vec<String>, pattern: Regex },
+  /// The element, or one of its ancestors, has one of the attributes with a
+  /// value containing one of the [`LogoHeuristics::keywords`]
+  AttributeContainsKeyword { attrs: Vec<String> },
+  /// The element, or one of its ancestors, has one of the attributes with a
+  /// value containing the site name: the name of the domain (decoded when it's
+  /// an IDN), the `og:site_name` or the name in the page's `<title>`. Spaces,
+  /// punctuation, case and diacritics are ignored.
+  AttributeContainsSiteName { attrs: Vec<String> },
+}
+
+/// A rule that adds `weight` to the candidates it applies to
+#[derive(Debug, Clone)]
+pub struct LogoRule {
+  pub name: String,
+  pub weight: i32,
+  pub condition: LogoCondition,
+}
+
+impl LogoRule {
+  pub fn new<N: Into<String>>(name: N, weight: i32, condition: LogoCondition) -> Self {
+    LogoRule {
+      name: name.into(),
+      weight,
+      condition,
+    }
+  }
+
+  pub fn inside<N: Into<String>, S: Into<String>>(name: N, weight: i32, selector: S) -> Self {
+    LogoRule::new(name, weight, LogoCondition::Inside(selector.into()))
+  }
+
+  pub fn attribute_matches<N: Into<String>>(
+    name: N,
+    weight: i32,
+    attrs: &[&str],
+    pattern: Regex,
+  ) -> Self {
+    LogoRule::new(
+      name,
+      weight,
+      LogoCondition::AttributeMatches {
+        attrs: attrs.iter().map(|attr| attr.to_string()).collect(),
+        pattern,
+      },
+    )
+  }
+
+  pub fn attribute_contains_keyword<N: Into<String>>(name: N, weight: i32, attrs: &[&str]) -> Self {
+    LogoRule::new(
+      name,
+      weight,
+      LogoCondition::AttributeContainsKeyword {
+        attrs: attrs.iter().map(|attr| attr.to_string()).collect(),
+      },
+    )
+  }
+
+  pub fn attribute_contains_site_name<N: Into<String>>(
+    name: N,
+    weight: i32,
+    attrs: &[&str],
+  ) -> Self {
+    LogoRule::new(
+      name,
+      weight,
+      LogoCondition::AttributeContainsSiteName {
+        attrs: attrs.iter().map(|attr| attr.to_string()).collect(),
+      },
+    )
+  }
+}
+
+/// How the site logo is picked from the `<img>`, `<svg>`, `<object>` and
+/// `<embed>` elements of a page, see [`SiteIcons::with_logo_heuristics`](crate::SiteIcons::with_logo_heuristics)
+#[derive(Debug, Clone)]
+pub struct LogoHeuristics {
+  /// CSS selectors for the candidate `<img>`, `<svg>`, `<object>` and `<embed>` elements
+  pub selectors: Vec<String>,
+  /// CSS selectors for elements whose CSS `background-image` can be the
+  /// logo, e.g. `<a class="logo" style="background-image: url(...)">`
+  pub background_selectors: Vec<String>,
+  /// Words for "logo", matched at the start of words and compared case- and
+  /// diacritic-insensitively, so `logo` also matches `logotipo` but not a list
+  /// of partner `logos`. Besides the
+  /// `selectors`, images with a keyword in their `src`, `data`, `alt`, `class` or `id`,
+  /// or inside an element with one in its `class` or `id`, are candidates, as
+  /// are elements with one in their `class` or `id` that have a background image.
+  pub keywords: Vec<String>,
+  /// Candidates inside an element with a (lowercased) class matching the
+  /// pattern are skipped. The id is checked for elements without a class.
+  pub skip_pattern: Regex,
+  pub rules: Vec<LogoRule>,
+  /// The weight of logos declared through schema.org
+  pub schema_org_weight: i32,
+  /// The weight of the `u-logo` of the representative h-card
+  pub h_card_logo_weight: i32,
+  /// The weight of the `u-photo` of the representative h-card
+  pub h_card_photo_weight: i32,
+  /// Stop downloading the page once the best candidate found so far has at
+  /// least this weight. `None` always reads the whole page.
+  pub decisive_weight: Option<i32>,
+  /// Stop downloading the page after this many bytes. `None` reads all of it.
+  pub max_bytes: Option<usize>,
+}
+
+impl Default for LogoHeuristics {
+  fn default() -> Self {
+    LogoHeuristics {
+      selectors: [
+        "a[href='/'] img, a[href='/'] svg, a[href='/'] object, a[href='/'] embed",
+        "header img, header svg, header object, header embed",
+        "img[src*=logo], img[srcset*=logo], object[data*=logo], embed[src*=logo]",
+        "img[data-src*=logo], img[data-lazy-src*=logo], img[data-original*=logo]",
+        "img[alt*=logo], svg[alt*=logo]",
+        "*[class*=logo] img, *[class*=logo] svg, *[class*=logo] object, *[class*=logo] embed",
+        "*[id*=logo] img, *[id*=logo] svg, *[id*=logo] object, *[id*=logo] embed",
+        "img[class*=logo], svg[class*=logo]",
+        "img[id*=logo], svg[id*=logo]",
+      ]
+      .iter()
+      .map(|selector| selector.to_string())
+      .collect(),
+      background_selectors: ["a[href='/']", "*[class*=logo]", "*[id*=logo]"]
+        .iter()
+        .map(|selector| selector.to_string())
+        .collect(),
+      keywords: [
+        "logo",
+        "marque",
+        "лого",
+        "ロゴ",
+        "标志",
+        "標誌",
+        "로고",
+        "شعار",
+        "לוגו",
+        "λογότυπο",
+      ]
+      .iter()
+      .map(|keyword| keyword.to_string())
+      .collect(),
+      skip_pattern: Regex::new("menu|search").unwrap(),
+      rules: vec![
+        LogoRule::inside("header", 2, "header"),
+        LogoRule::new("first_match", 1, LogoCondition::FirstMatch),
+        LogoRule::attribute_matches("links_home", 5, &["href"], Regex::new("^/$").unwrap()),
+        LogoRule::attribute_contains_keyword("logo_class_or_id", 3, &["class", "id"]),
+        LogoRule::attribute_contains_keyword("logo_alt", 2, &["alt"]),
+        LogoRule::attribute_contains_keyword(
+          "logo_src",
+          1,
+          &[
+            "src",
+            "srcset",
+            "data",
+            "data-src",
+            "data-lazy-src",
+            "data-original",
+          ],
+        ),
+        LogoRule::attribute_contains_site_name("alt_site_name", 10, &["alt"]),
+        LogoRule::attribute_contains_site_name("label_site_name", 6, &["aria-label", "title"]),
+      ],
+      schema_org_weight: 20,
+      h_card_logo_weight: 20,
+      h_card_photo_weight: 18,
+      // e.g. a home link in the header with the site name as alt text
+      decisive_weight: Some(18),
+      max_bytes: None,
+    }
+  }
+}
+
+impl LogoHeuristics {
+  /// Adds rules for ARIA landmarks and labels: `role=banner` and `<nav>`
+  /// ancestors, and an `aria-label` mentioning the logo or the site name.
+  /// Images inside banners and labelled links become candidates too.
+  pub fn with_aria_rules(mut self) -> Self {
+    self.selectors.extend([
+      "[role=banner] img, [role=banner] svg, [role=banner] object, [role=banner] embed".to_string(),
+      "a[aria-label] img, a[aria-label] svg, a[aria-label] object, a[aria-label] embed".to_string(),
+    ]);
+
+    self.rules.extend([
+      LogoRule::inside("banner_landmark", 2, "[role=banner]"),
+      LogoRule::inside("nav_landmark", 1, "nav, [role=navigation]"),
+      LogoRule::attribute_contains_keyword("logo_aria_label", 2, &["aria-label"]),
+      LogoRule::attribute_contains_site_name("aria_label_site_name", 10, &["aria-label"]),
+    ]);
+
+    self
+  }
+}
+
+/// A site logo candidate along with how it was scored, see
+/// [`IconCandidate::score_site_logos`]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct ScoredLogo {
+  pub href: Url,
+  /// The element's tag, e.g. `img`, `svg` or `object`. `None` for logos declared
+  /// through schema.org or an h-card.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub tag: Option<String>,
+  /// A CSS selector path to the element, e.g. `html > body > header > a:nth-of-type(2) > img`
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub selector_path: Option<String>,
+  /// The `alt` text of an `<img>`, the `<title>` of an `<svg>`, or the label
+  /// of other elements
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub alt: Option<String>,
+  /// The sum of the weights of the rules that applied
+  pub weight: i32,
+  /// The name and weight of each rule that applied
+  pub rules: Vec<(String, i32)>,
+}
+
+impl ScoredLogo {
+  /// The candidate to [resolve](IconCandidate::resolve) for this logo
+  pub fn to_candidate(&self) -> IconCandidate {
+    IconCandidate {
+      href: self.href.clone(),
+      kind: IconKind::SiteLogo,
+      rel: None,
+      sizes: None,
+      mime_type: None,
+      media: None,
+      purpose: Vec::new(),
+      source: IconSource::PageContent,
+    }
+  }
+}
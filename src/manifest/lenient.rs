@@ -0,0 +1,548 @@
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::fmt::{self, Display};
+
+/// A fix applied to a manifest that wasn't valid JSON, or didn't match the spec
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ManifestRepair {
+  /// Stripped a UTF-8 byte order mark
+  ByteOrderMark,
+  /// Removed `//` or `/* */` comments
+  Comments,
+  /// Removed trailing commas in objects or arrays
+  TrailingCommas,
+  /// Wrapped an `icons` object in an array
+  SingleIcon,
+  /// Converted numeric `sizes` (e.g. `192`) to `192x192`
+  NumericSizes,
+  /// Joined an array of `sizes` into a space separated list
+  SizesArray,
+  /// Trimmed whitespace around an icon `src`
+  SrcWhitespace,
+  /// Dropped icon entries that weren't objects with a `src`
+  InvalidIcons,
+  /// Joined an array of `purpose` values into a space separated list
+  PurposeArray,
+  /// Dropped icon `sizes`, `type` or `purpose` fields that weren't strings
+  InvalidIconFields,
+  /// Dropped `name`, `short_name` or color fields that weren't strings
+  InvalidMetadata,
+  /// Converted numeric `related_applications` ids to strings
+  NumericAppIds,
+  /// Dropped `related_applications` entries without a `platform`
+  InvalidRelatedApplications,
+}
+
+impl Display for ManifestRepair {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    f.write_str(match self {
+      ManifestRepair::ByteOrderMark => "stripped byte order mark",
+      ManifestRepair::Comments => "removed comments",
+      ManifestRepair::TrailingCommas => "removed trailing commas",
+      ManifestRepair::SingleIcon => "wrapped single icon object in an array",
+      ManifestRepair::NumericSizes => "converted numeric sizes",
+      ManifestRepair::SizesArray => "joined sizes array",
+      ManifestRepair::SrcWhitespace => "trimmed whitespace around icon src",
+      ManifestRepair::InvalidIcons => "dropped invalid icon entries",
+      ManifestRepair::PurposeArray => "joined purpose array",
+      ManifestRepair::InvalidIconFields => "dropped invalid icon fields",
+      ManifestRepair::InvalidMetadata => "dropped invalid metadata fields",
+      ManifestRepair::NumericAppIds => "converted numeric app ids",
+      ManifestRepair::InvalidRelatedApplications => "dropped invalid related applications",
+    })
+  }
+}
+
+/// The manifest fields that only carry metadata about the site
+const METADATA_FIELDS: &[&str] = &["name", "short_name", "theme_color", "background_color"];
+
+/// Parses manifest JSON, repairing the mistakes commonly found in the wild
+pub fn parse_lenient(bytes: &[u8]) -> Result<(Value, Vec<ManifestRepair>), serde_json::Error> {
+  let mut repairs = Vec::new();
+
+  let bytes = match bytes.strip_prefix(b"\xEF\xBB\xBF") {
+    Some(bytes) => {
+      repairs.push(ManifestRepair::ByteOrderMark);
+      bytes
+    }
+    None => bytes,
+  };
+
+  let mut value = match serde_json::from_slice(bytes) {
+    Ok(value) => value,
+    Err(err) => {
+      let json = String::from_utf8_lossy(bytes);
+
+      let (json, removed_comments) = strip_comments(&json);
+      if removed_comments {
+        repairs.push(ManifestRepair::Comments);
+      }
+
+      let (json, removed_commas) = strip_trailing_commas(&json);
+      if removed_commas {
+        repairs.push(ManifestRepair::TrailingCommas);
+      }
+
+      if !removed_comments && !removed_commas {
+        return Err(err);
+      }
+
+      serde_json::from_str(&json)?
+    }
+  };
+
+  if let Value::Object(manifest) = &mut value {
+    // metadata is optional, it shouldn't cost the icons
+    for field in METADATA_FIELDS {
+      if !matches!(
+        manifest.get(*field),
+        None | Some(Value::String(_)) | Some(Value::Null)
+      ) {
+        manifest.remove(*field);
+        repairs.push(ManifestRepair::InvalidMetadata);
+      }
+    }
+
+    repair_icons(manifest, &mut repairs);
+    repair_related_applications(manifest, &mut repairs);
+
+    if let Some(Value::Array(shortcuts)) = manifest.get_mut("shortcuts") {
+      for shortcut in shortcuts {
+        if let Value::Object(shortcut) = shortcut {
+          repair_icons(shortcut, &mut repairs);
+        }
+      }
+    }
+  }
+
+  Ok((value, repairs.into_iter().unique().collect()))
+}
+
+fn repair_icons(object: &mut Map<String, Value>, repairs: &mut Vec<ManifestRepair>) {
+  match object.get_mut("icons") {
+    None | Some(Value::Array(_)) => {}
+    Some(icons @ Value::Object(_)) => {
+      *icons = Value::Array(vec![icons.take()]);
+      repairs.push(ManifestRepair::SingleIcon);
+    }
+    Some(_) => {
+      object.remove("icons");
+      repairs.push(ManifestRepair::InvalidIcons);
+    }
+  }
+
+  let icons = match object.get_mut("icons") {
+    Some(Value::Array(icons)) => icons,
+    _ => return,
+  };
+
+  let len = icons.len();
+  icons.retain(|icon| matches!(icon.get("src"), Some(Value::String(_))));
+  if icons.len() != len {
+    repairs.push(ManifestRepair::InvalidIcons);
+  }
+
+  for icon in icons {
+    if let Some(Value::String(src)) = icon.get_mut("src") {
+      if src.trim().len() != src.len() {
+        *src = src.trim().to_string();
+        repairs.push(ManifestRepair::SrcWhitespace);
+      }
+    }
+
+    if let Some(sizes) = icon.get_mut("sizes") {
+      match sizes {
+        Value::Number(size) => {
+          *sizes = Value::String(format!("{0}x{0}", size));
+          repairs.push(ManifestRepair::NumericSizes);
+        }
+        Value::Array(values) => {
+          let joined = values
+            .iter()
+            .filter_map(|size| match size {
+              Value::String(size) => Some(size.clone()),
+              Value::Number(size) => Some(format!("{0}x{0}", size)),
+              _ => None,
+            })
+            .collect::<Vec<_>>()
+            .join(" ");
+
+          *sizes = Value::String(joined);
+          repairs.push(ManifestRepair::SizesArray);
+        }
+        _ => {}
+      }
+    }
+
+    if let Some(Value::Array(values)) = icon.get("purpose") {
+      let joined = values
+        .iter()
+        .filter_map(Value::as_str)
+        .collect::<Vec<_>>()
+        .join(" ");
+
+      icon["purpose"] = Value::String(joined);
+      repairs.push(ManifestRepair::PurposeArray);
+    }
+
+    if let Value::Object(icon) = icon {
+      let len = icon.len();
+      icon.retain(|field, value| {
+        !matches!(field.as_str(), "sizes" | "type" | "purpose")
+          || matches!(value, Value::String(_) | Value::Null)
+      });
+
+      if icon.len() != len {
+        repairs.push(ManifestRepair::InvalidIconFields);
+      }
+    }
+  }
+}
+
+fn repair_related_applications(
+  manifest: &mut Map<String, Value>,
+  repairs: &mut Vec<ManifestRepair>,
+) {
+  let apps = match manifest.get_mut("related_applications") {
+    None => return,
+    Some(Value::Array(apps)) => apps,
+    Some(_) => {
+      manifest.remove("related_applications");
+      repairs.push(ManifestRepair::InvalidRelatedApplications);
+      return;
+    }
+  };
+
+  let len = apps.len();
+  apps.retain(|app| matches!(app.get("platform"), Some(Value::String(_))));
+  if apps.len() != len {
+    repairs.push(ManifestRepair::InvalidRelatedApplications);
+  }
+
+  for app in apps.iter_mut().filter_map(Value::as_object_mut) {
+    match app.get("id") {
+      Some(Value::Number(id)) => {
+        let id = id.to_string();
+        app.insert("id".to_string(), Value::String(id));
+        repairs.push(ManifestRepair::NumericAppIds);
+      }
+      None | Some(Value::String(_)) | Some(Value::Null) => {}
+      Some(_) => {
+        app.remove("id");
+        repairs.push(ManifestRepair::InvalidRelatedApplications);
+      }
+    }
+
+    if !matches!(
+      app.get("url"),
+      None | Some(Value::String(_)) | Some(Value::Null)
+    ) {
+      app.remove("url");
+      repairs.push(ManifestRepair::InvalidRelatedApplications);
+    }
+  }
+}
+
+/// Removes `//` and `/* */` comments outside of strings
+fn strip_comments(json: &str) -> (String, bool) {
+  let mut result = String::with_capacity(json.len());
+  let mut chars = json.chars().peekable();
+  let mut in_string = false;
+  let mut removed = false;
+
+  while let Some(c) = chars.next() {
+    if in_string {
+      result.push(c);
+
+      match c {
+        '\\' => result.extend(chars.next()),
+        '"' => in_string = false,
+        _ => {}
+      }
+
+      continue;
+    }
+
+    match (c, chars.peek()) {
+      ('"', _) => {
+        in_string = true;
+        result.push(c);
+      }
+      ('/', Some('/')) => {
+        removed = true;
+        for c in chars.by_ref() {
+          if c == '\n' {
+            result.push(c);
+            break;
+          }
+        }
+      }
+      ('/', Some('*')) => {
+        removed = true;
+        chars.next();
+
+        let mut prev = None;
+        for c in chars.by_ref() {
+          if prev == Some('*') && c == '/' {
+            break;
+          }
+          prev = Some(c);
+        }
+      }
+      _ => result.push(c),
+    }
+  }
+
+  (result, removed)
+}
+
+/// Removes commas directly followed by the end of an object or array
+fn strip_trailing_commas(json: &str) -> (String, bool) {
+  let chars = json.chars().collect::<Vec<_>>();
+  let mut result = String::with_capacity(json.len());
+  let mut in_string = false;
+  let mut escaped = false;
+  let mut removed = false;
+
+  for (i, &c) in chars.iter().enumerate() {
+    if in_string {
+      match c {
+        _ if escaped => escaped = false,
+        '\\' => escaped = true,
+        '"' => in_string = false,
+        _ => {}
+      }
+    } else if c == '"' {
+      in_string = true;
+    } else if c == ',' {
+      let next = chars[i + 1..].iter().find(|c| !c.is_whitespace());
+
+      if matches!(next, Some(']') | Some('}') | None) {
+        removed = true;
+        continue;
+      }
+    }
+
+    result.push(c);
+  }
+
+  (result, removed)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use serde_json::json;
+
+  fn repairs_of(json: &[u8]) -> Vec<ManifestRepair> {
+    parse_lenient(json).unwrap().1
+  }
+
+  #[test]
+  fn leaves_valid_manifests_alone() {
+    let (value, repairs) =
+      parse_lenient(br#"{ "icons": [{ "src": "a.png", "sizes": "192x192" }] }"#).unwrap();
+
+    assert_eq!(
+      value,
+      json!({ "icons": [{ "src": "a.png", "sizes": "192x192" }] })
+    );
+    assert_eq!(repairs, vec![]);
+  }
+
+  #[test]
+  fn strips_byte_order_mark() {
+    assert_eq!(
+      repairs_of(b"\xEF\xBB\xBF{ \"name\": \"Example\" }"),
+      vec![ManifestRepair::ByteOrderMark]
+    );
+  }
+
+  #[test]
+  fn removes_comments() {
+    let (value, repairs) = parse_lenient(
+      br#"{
+        // the app name
+        "name": "http://example.com/*", /* not a comment: */
+        "short_name": "/* kept */"
+      }"#,
+    )
+    .unwrap();
+
+    assert_eq!(
+      value,
+      json!({ "name": "http://example.com/*", "short_name": "/* kept */" })
+    );
+    assert_eq!(repairs, vec![ManifestRepair::Comments]);
+  }
+
+  #[test]
+  fn removes_trailing_commas() {
+    let (value, repairs) =
+      parse_lenient(br#"{ "name": "a,}", "icons": [{ "src": "a.png", }, ], }"#).unwrap();
+
+    assert_eq!(
+      value,
+      json!({ "name": "a,}", "icons": [{ "src": "a.png" }] })
+    );
+    assert_eq!(repairs, vec![ManifestRepair::TrailingCommas]);
+  }
+
+  #[test]
+  fn keeps_syntax_errors() {
+    assert!(parse_lenient(br#"{ "name": }"#).is_err());
+  }
+
+  #[test]
+  fn wraps_single_icon() {
+    let (value, repairs) = parse_lenient(br#"{ "icons": { "src": "a.png" } }"#).unwrap();
+
+    assert_eq!(value, json!({ "icons": [{ "src": "a.png" }] }));
+    assert_eq!(repairs, vec![ManifestRepair::SingleIcon]);
+  }
+
+  #[test]
+  fn converts_numeric_sizes() {
+    let (value, repairs) =
+      parse_lenient(br#"{ "icons": [{ "src": "a.png", "sizes": 192 }] }"#).unwrap();
+
+    assert_eq!(
+      value,
+      json!({ "icons": [{ "src": "a.png", "sizes": "192x192" }] })
+    );
+    assert_eq!(repairs, vec![ManifestRepair::NumericSizes]);
+  }
+
+  #[test]
+  fn joins_sizes_array() {
+    let (value, repairs) = parse_lenient(
+      br#"{ "shortcuts": [{ "icons": [{ "src": "a.png", "sizes": ["16x16", 32, null] }] }] }"#,
+    )
+    .unwrap();
+
+    assert_eq!(
+      value,
+      json!({ "shortcuts": [{ "icons": [{ "src": "a.png", "sizes": "16x16 32x32" }] }] })
+    );
+    assert_eq!(repairs, vec![ManifestRepair::SizesArray]);
+  }
+
+  #[test]
+  fn trims_src_whitespace() {
+    let (value, repairs) = parse_lenient(b"{ \"icons\": [{ \"src\": \" a.png\\n\" }] }").unwrap();
+
+    assert_eq!(value, json!({ "icons": [{ "src": "a.png" }] }));
+    assert_eq!(repairs, vec![ManifestRepair::SrcWhitespace]);
+  }
+
+  #[test]
+  fn drops_invalid_icons() {
+    let (value, repairs) =
+      parse_lenient(br#"{ "icons": ["a.png", { "src": 1 }, { "src": "b.png" }] }"#).unwrap();
+
+    assert_eq!(value, json!({ "icons": [{ "src": "b.png" }] }));
+    assert_eq!(repairs, vec![ManifestRepair::InvalidIcons]);
+
+    assert_eq!(
+      repairs_of(br#"{ "icons": "a.png" }"#),
+      vec![ManifestRepair::InvalidIcons]
+    );
+  }
+
+  #[test]
+  fn joins_purpose_array() {
+    let (value, repairs) =
+      parse_lenient(br#"{ "icons": [{ "src": "a.png", "purpose": ["any", "maskable", 1] }] }"#)
+        .unwrap();
+
+    assert_eq!(
+      value,
+      json!({ "icons": [{ "src": "a.png", "purpose": "any maskable" }] })
+    );
+    assert_eq!(repairs, vec![ManifestRepair::PurposeArray]);
+  }
+
+  #[test]
+  fn drops_invalid_icon_fields() {
+    let (value, repairs) = parse_lenient(
+      br#"{ "icons": [{ "src": "a.png", "sizes": true, "type": 1, "purpose": {}, "density": 2 }] }"#,
+    )
+    .unwrap();
+
+    assert_eq!(
+      value,
+      json!({ "icons": [{ "src": "a.png", "density": 2 }] })
+    );
+    assert_eq!(repairs, vec![ManifestRepair::InvalidIconFields]);
+  }
+
+  #[test]
+  fn drops_invalid_metadata() {
+    let (value, repairs) = parse_lenient(
+      br#"{ "name": "Example", "short_name": ["Ex"], "theme_color": 5, "background_color": null }"#,
+    )
+    .unwrap();
+
+    assert_eq!(
+      value,
+      json!({ "name": "Example", "background_color": null })
+    );
+    assert_eq!(repairs, vec![ManifestRepair::InvalidMetadata]);
+  }
+
+  #[test]
+  fn converts_numeric_app_ids() {
+    let (value, repairs) =
+      parse_lenient(br#"{ "related_applications": [{ "platform": "itunes", "id": 123456 }] }"#)
+        .unwrap();
+
+    assert_eq!(
+      value,
+      json!({ "related_applications": [{ "platform": "itunes", "id": "123456" }] })
+    );
+    assert_eq!(repairs, vec![ManifestRepair::NumericAppIds]);
+  }
+
+  #[test]
+  fn drops_invalid_related_applications() {
+    let (value, repairs) = parse_lenient(
+      br#"{ "related_applications": [
+        { "id": "com.example.app" },
+        "play",
+        { "platform": "play", "id": true, "url": "https://play.google.com/" },
+        { "platform": "webapp", "url": {} }
+      ] }"#,
+    )
+    .unwrap();
+
+    assert_eq!(
+      value,
+      json!({ "related_applications": [
+        { "platform": "play", "url": "https://play.google.com/" },
+        { "platform": "webapp" }
+      ] })
+    );
+    assert_eq!(repairs, vec![ManifestRepair::InvalidRelatedApplications]);
+
+    assert_eq!(
+      repairs_of(br#"{ "related_applications": { "platform": "play" } }"#),
+      vec![ManifestRepair::InvalidRelatedApplications]
+    );
+  }
+
+  #[test]
+  fn reports_each_repair_once() {
+    assert_eq!(
+      repairs_of(
+        br#"{ "icons": [{ "src": " a.png", "sizes": 16, }, { "src": "b.png ", "sizes": 32 }] }"#
+      ),
+      vec![
+        ManifestRepair::TrailingCommas,
+        ManifestRepair::SrcWhitespace,
+        ManifestRepair::NumericSizes,
+      ]
+    );
+  }
+}
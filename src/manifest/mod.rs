@@ -0,0 +1,371 @@
+mod lenient;
+
+pub use lenient::ManifestRepair;
+
+use crate::{
+  Diagnostic, Icon, IconCandidate, IconInfo, IconKind, IconPurpose, IconSource, NativeApp,
+  SiteIcons, CLIENT,
+};
+use cached::proc_macro::cached;
+use futures::future::join_all;
+use itertools::Itertools;
+use reqwest::IntoUrl;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use url::Url;
+
+/// An entry of the manifest [`icons`](https://developer.mozilla.org/en-US/docs/Web/Manifest/icons) field
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ManifestIcon {
+  pub src: String,
+  pub sizes: Option<String>,
+  #[serde(rename = "type")]
+  pub mime_type: Option<String>,
+  pub purpose: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ManifestShortcut {
+  #[serde(default)]
+  pub icons: Vec<ManifestIcon>,
+}
+
+/// An entry of the manifest `related_applications` field
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ManifestRelatedApplication {
+  pub platform: String,
+  pub url: Option<String>,
+  pub id: Option<String>,
+}
+
+/// A [web app manifest](https://developer.mozilla.org/en-US/docs/Web/Manifest),
+/// with only the fields that are relevant to icons
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Manifest {
+  pub name: Option<String>,
+  pub short_name: Option<String>,
+  pub theme_color: Option<String>,
+  pub background_color: Option<String>,
+  #[serde(default)]
+  pub icons: Vec<ManifestIcon>,
+  #[serde(default)]
+  pub shortcuts: Vec<ManifestShortcut>,
+  #[serde(default)]
+  pub related_applications: Vec<ManifestRelatedApplication>,
+}
+
+impl Manifest {
+  /// Parses manifest JSON, repairing common mistakes along the way
+  pub fn parse(json: &[u8]) -> Result<(Manifest, Vec<ManifestRepair>), serde_json::Error> {
+    let (manifest, repairs) = lenient::parse_lenient(json)?;
+
+    Ok((serde_json::from_value(manifest)?, repairs))
+  }
+
+  pub fn metadata(&self) -> SiteMetadata {
+    SiteMetadata {
+      name: self.name.clone(),
+      short_name: self.short_name.clone(),
+      theme_color: self.theme_color.clone(),
+      background_color: self.background_color.clone(),
+    }
+  }
+
+  /// The `related_applications`, with their URLs resolved against the manifest `url`
+  pub fn native_apps(&self, url: &Url) -> Vec<NativeApp> {
+    self
+      .related_applications
+      .iter()
+      .filter(|app| app.id.is_some() || app.url.is_some())
+      .map(|app| NativeApp {
+        platform: app.platform.trim().to_ascii_lowercase(),
+        id: app.id.clone(),
+        url: app.url.as_ref().and_then(|href| url.join(href.trim()).ok()),
+        source: IconSource::Manifest(url.clone()),
+      })
+      .collect()
+  }
+
+  /// The icons and shortcut icons, resolved against the manifest `url`.
+  /// Icons with an unsupported type, or only unknown purposes are left out.
+  pub fn icon_candidates(&self, url: &Url) -> Vec<IconCandidate> {
+    let icons = self
+      .icons
+      .iter()
+      .map(|icon| (icon, IconKind::AppIcon))
+      .chain(
+        self
+          .shortcuts
+          .iter()
+          .flat_map(|shortcut| &shortcut.icons)
+          .map(|icon| (icon, IconKind::AppShortcutIcon)),
+      );
+
+    icons
+      .filter_map(|(icon, kind)| {
+        if let Some(mime_type) = &icon.mime_type {
+          if !IconInfo::is_supported_type(mime_type) {
+            return None;
+          }
+        }
+
+        // icons that only declare unknown purposes must be ignored
+        let purpose = match &icon.purpose {
+          Some(purpose) => match IconPurpose::parse_list(purpose) {
+            purpose if purpose.is_empty() => return None,
+            purpose => purpose,
+          },
+          None => Vec::new(),
+        };
+
+        Some(IconCandidate {
+          href: url.join(&icon.src).ok()?,
+          kind,
+          rel: None,
+          sizes: icon.sizes.clone(),
+          mime_type: icon.mime_type.clone(),
+          media: None,
+          purpose,
+          source: IconSource::Manifest(url.clone()),
+        })
+      })
+      .collect()
+  }
+}
+
+/// Metadata about the site, taken from its web app manifest
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct SiteMetadata {
+  pub name: Option<String>,
+  pub short_name: Option<String>,
+  pub theme_color: Option<String>,
+  pub background_color: Option<String>,
+}
+
+impl SiteMetadata {
+  /// Fills in the fields that are missing from `other`
+  pub fn merge(&mut self, other: &SiteMetadata) {
+    fn fill(field: &mut Option<String>, other: &Option<String>) {
+      if field.is_none() {
+        *field = other.clone();
+      }
+    }
+
+    fill(&mut self.name, &other.name);
+    fill(&mut self.short_name, &other.short_name);
+    fill(&mut self.theme_color, &other.theme_color);
+    fill(&mut self.background_color, &other.background_color);
+  }
+}
+
+#[derive(Debug, Clone, Default)]
+pub(crate) struct LoadedManifest {
+  pub icons: Vec<Icon>,
+  pub metadata: SiteMetadata,
+  pub apps: Vec<NativeApp>,
+  pub diagnostics: Vec<Diagnostic>,
+}
+
+impl LoadedManifest {
+  /// Merges several manifests, in order of precedence. Icons are deduplicated
+  /// by their resolved URL, keeping the first manifest they were found in.
+  pub fn merge(manifests: impl IntoIterator<Item = LoadedManifest>) -> LoadedManifest {
+    let mut merged = LoadedManifest::default();
+
+    for manifest in manifests {
+      merged.metadata.merge(&manifest.metadata);
+      merged.icons.extend(manifest.icons);
+      merged.apps.extend(manifest.apps);
+      merged.diagnostics.extend(manifest.diagnostics);
+    }
+
+    merged.icons = merged
+      .icons
+      .into_iter()
+      .unique_by(|icon| icon.url.clone())
+      .collect();
+
+    merged
+  }
+}
+
+/// A manifest that was fetched, without loading its icons
+#[derive(Debug, Clone, Default)]
+pub(crate) struct DiscoveredManifest {
+  pub candidates: Vec<IconCandidate>,
+  pub metadata: SiteMetadata,
+  pub apps: Vec<NativeApp>,
+  pub diagnostics: Vec<Diagnostic>,
+}
+
+impl SiteIcons {
+  pub async fn load_manifest<U: IntoUrl>(url: U) -> Result<Vec<Icon>, Box<dyn Error>> {
+    Ok(SiteIcons::load_manifest_with_metadata(url).await?.icons)
+  }
+
+  pub(crate) async fn load_manifest_with_metadata<U: IntoUrl>(
+    url: U,
+  ) -> Result<LoadedManifest, Box<dyn Error>> {
+    let url = url.into_url()?;
+
+    Ok(load_manifest_cached(url).await?)
+  }
+
+  pub(crate) async fn discover_manifest(url: Url) -> Result<DiscoveredManifest, Box<dyn Error>> {
+    Ok(discover_manifest_cached(url).await?)
+  }
+}
+
+#[cached(sync_writes = true)]
+async fn discover_manifest_cached(url: Url) -> Result<DiscoveredManifest, String> {
+  let url = &url;
+
+  let body = CLIENT
+    .get(url.clone())
+    .send()
+    .await
+    .map_err(|e| format!("{}: {:?}", url, e))?
+    .error_for_status()
+    .map_err(|e| format!("{}: {:?}", url, e))?
+    .bytes()
+    .await
+    .map_err(|e| format!("{}: {:?}", url, e))?;
+
+  let (manifest, repairs) = Manifest::parse(&body).map_err(|e| format!("{}: {:?}", url, e))?;
+
+  let diagnostics = repairs
+    .into_iter()
+    .map(|repair| {
+      info!("{}: {}", url, repair);
+
+      Diagnostic::ManifestRepaired {
+        url: url.clone(),
+        repair,
+      }
+    })
+    .collect();
+
+  Ok(DiscoveredManifest {
+    candidates: manifest.icon_candidates(url),
+    metadata: manifest.metadata(),
+    apps: manifest.native_apps(url),
+    diagnostics,
+  })
+}
+
+#[cached(sync_writes = true)]
+async fn load_manifest_cached(url: Url) -> Result<LoadedManifest, String> {
+  let manifest = discover_manifest_cached(url).await?;
+
+  let icons = join_all(
+    manifest
+      .candidates
+      .into_iter()
+      .map(|candidate| async { candidate.resolve().await.ok() }),
+  )
+  .await
+  .into_iter()
+  .flatten()
+  .collect();
+
+  Ok(LoadedManifest {
+    icons,
+    metadata: manifest.metadata,
+    apps: manifest.apps,
+    diagnostics: manifest.diagnostics,
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn keeps_icons_of_invalid_metadata() {
+    let url = Url::parse("https://example.com/manifest.json").unwrap();
+    let (manifest, _) = Manifest::parse(
+      br#"{
+        "name": "Example",
+        "theme_color": 5,
+        "icons": [{ "src": "icon.png", "sizes": "192x192", "purpose": ["any", "maskable"] }]
+      }"#,
+    )
+    .unwrap();
+
+    assert_eq!(manifest.name.as_deref(), Some("Example"));
+    assert_eq!(manifest.theme_color, None);
+    assert_eq!(
+      manifest.icon_candidates(&url)[0].purpose,
+      vec![IconPurpose::Any, IconPurpose::Maskable]
+    );
+  }
+
+  #[test]
+  fn keeps_icons_of_invalid_related_applications() {
+    let url = Url::parse("https://example.com/manifest.json").unwrap();
+    let (manifest, repairs) = Manifest::parse(
+      br#"{
+        "icons": [{ "src": "icon.png", "sizes": "192x192" }],
+        "related_applications": [{ "platform": "itunes", "id": 123456 }, { "id": "com.example.app" }]
+      }"#,
+    )
+    .unwrap();
+
+    assert_eq!(manifest.icon_candidates(&url).len(), 1);
+    assert_eq!(manifest.native_apps(&url)[0].id.as_deref(), Some("123456"));
+    assert_eq!(
+      repairs,
+      vec![
+        ManifestRepair::InvalidRelatedApplications,
+        ManifestRepair::NumericAppIds,
+      ]
+    );
+  }
+
+  #[test]
+  fn keeps_scalable_icons() {
+    let url = Url::parse("https://example.com/manifest.json").unwrap();
+    let (manifest, _) = Manifest::parse(
+      br#"{ "icons": [{ "src": "icon.svg", "sizes": "any" }, { "src": "icon.png", "sizes": 192 }] }"#,
+    )
+    .unwrap();
+
+    let candidates = manifest.icon_candidates(&url);
+    assert_eq!(candidates[0].sizes.as_deref(), Some("any"));
+    assert!(candidates[0].is_scalable());
+    assert!(!candidates[1].is_scalable());
+  }
+
+  #[test]
+  fn resolves_related_applications() {
+    let url = Url::parse("https://example.com/app/manifest.json").unwrap();
+    let (manifest, _) = Manifest::parse(
+      br#"{
+        "related_applications": [
+          { "platform": " Play ", "id": "com.example.app" },
+          { "platform": "itunes", "url": " /store/id123 " },
+          { "platform": "webapp" }
+        ]
+      }"#,
+    )
+    .unwrap();
+
+    assert_eq!(
+      manifest.native_apps(&url),
+      vec![
+        NativeApp {
+          platform: "play".to_string(),
+          id: Some("com.example.app".to_string()),
+          url: None,
+          source: IconSource::Manifest(url.clone()),
+        },
+        NativeApp {
+          platform: "itunes".to_string(),
+          id: None,
+          url: Some(Url::parse("https://example.com/store/id123").unwrap()),
+          source: IconSource::Manifest(url.clone()),
+        },
+      ]
+    );
+  }
+}
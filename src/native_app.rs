@@ -0,0 +1,270 @@
+use crate::{IconCandidate, IconKind, IconSource, SiteIcons, CLIENT};
+use futures::future::{join_all, FutureExt, LocalBoxFuture};
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use url::Url;
+
+/// A native app published by the site, declared through a smart app banner
+/// `<meta>` tag or the manifest [`related_applications`](https://developer.mozilla.org/en-US/docs/Web/Manifest/related_applications)
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct NativeApp {
+  /// The store platform, using the manifest vocabulary, e.g. `itunes` or `play`
+  pub platform: String,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub id: Option<String>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub url: Option<Url>,
+  pub source: IconSource,
+}
+
+impl NativeApp {
+  /// Parses a smart app banner `<meta name content>` tag, e.g.
+  /// `<meta name="apple-itunes-app" content="app-id=123, app-argument=...">`
+  pub fn from_meta(name: &str, content: &str) -> Option<NativeApp> {
+    let platform = match name.trim().to_ascii_lowercase().as_str() {
+      "apple-itunes-app" => "itunes",
+      "google-play-app" => "play",
+      _ => return None,
+    };
+
+    let id = content.split(',').find_map(|param| {
+      let (name, value) = param.split_once('=')?;
+
+      if name.trim().eq_ignore_ascii_case("app-id") && !value.trim().is_empty() {
+        Some(value.trim().to_string())
+      } else {
+        None
+      }
+    })?;
+
+    Some(NativeApp {
+      platform: platform.to_string(),
+      id: Some(id),
+      url: None,
+      source: IconSource::HeadTag,
+    })
+  }
+
+  /// Identifies the app regardless of where it was declared, by its store id
+  /// or otherwise its store URL
+  pub(crate) fn key(&self) -> (String, Option<String>) {
+    let id = self
+      .id
+      .clone()
+      .or_else(|| self.url.as_ref().map(|url| url.to_string()));
+
+    (self.platform.clone(), id)
+  }
+}
+
+/// Looks up the icon of a native app in its store, see
+/// [`SiteIcons::with_app_store_resolver`]
+pub trait AppStoreResolver {
+  /// The icon URL of the app, or `None` when the app's platform isn't supported
+  fn icon_url<'a>(
+    &'a self,
+    app: &'a NativeApp,
+  ) -> LocalBoxFuture<'a, Result<Option<Url>, Box<dyn Error>>>;
+}
+
+/// Resolves `itunes` apps through the public [iTunes lookup API](https://performance-partners.apple.com/search-api)
+#[derive(Debug, Clone, Default)]
+pub struct ItunesResolver {}
+
+#[derive(Debug, Deserialize)]
+struct ItunesLookup {
+  results: Vec<ItunesApp>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ItunesApp {
+  artwork_url512: Option<Url>,
+  artwork_url100: Option<Url>,
+}
+
+impl AppStoreResolver for ItunesResolver {
+  fn icon_url<'a>(
+    &'a self,
+    app: &'a NativeApp,
+  ) -> LocalBoxFuture<'a, Result<Option<Url>, Box<dyn Error>>> {
+    async move {
+      if app.platform != "itunes" {
+        return Ok(None);
+      }
+
+      // e.g. https://apps.apple.com/us/app/name/id123456789
+      let id = app.id.clone().or_else(|| {
+        let url = app.url.as_ref()?;
+        let captures = regex!(r"/id(\d+)").captures(url.path())?;
+        Some(captures[1].to_string())
+      });
+
+      let id = match id {
+        Some(id) => id.trim_start_matches("id").to_string(),
+        None => return Ok(None),
+      };
+
+      let mut url = Url::parse("https://itunes.apple.com/lookup").unwrap();
+      url.query_pairs_mut().append_pair("id", &id);
+
+      let lookup: ItunesLookup = CLIENT
+        .get(url)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+
+      Ok(
+        lookup
+          .results
+          .into_iter()
+          .find_map(|app| app.artwork_url512.or(app.artwork_url100)),
+      )
+    }
+    .boxed_local()
+  }
+}
+
+impl SiteIcons {
+  /// The store icons of the apps, when an [`AppStoreResolver`] is configured
+  pub(crate) async fn app_icon_candidates(&self, apps: &[NativeApp]) -> Vec<IconCandidate> {
+    let resolver = match &self.app_store_resolver {
+      Some(resolver) => resolver,
+      None => return Vec::new(),
+    };
+
+    join_all(apps.iter().map(|app| async move {
+      match resolver.icon_url(app).await {
+        Ok(href) => href,
+        Err(err) => {
+          info!("{} app {:?}: {}", app.platform, app.id, err);
+          None
+        }
+      }
+    }))
+    .await
+    .into_iter()
+    .flatten()
+    // e.g. an app declared by its id and by its store URL
+    .unique()
+    .filter(|href| !self.is_blacklisted(href))
+    .map(|href| IconCandidate {
+      href,
+      kind: IconKind::AppIcon,
+      rel: None,
+      sizes: None,
+      mime_type: None,
+      media: None,
+      purpose: Vec::new(),
+      source: IconSource::AppStore,
+    })
+    .collect()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use futures::future::ready;
+
+  #[test]
+  fn parses_smart_app_banners() {
+    let app = NativeApp::from_meta(
+      " Apple-iTunes-App",
+      "app-argument=https://example.com/, app-id = 123456789 ",
+    )
+    .unwrap();
+    assert_eq!(app.platform, "itunes");
+    assert_eq!(app.id.as_deref(), Some("123456789"));
+    assert_eq!(app.source, IconSource::HeadTag);
+
+    let app = NativeApp::from_meta("google-play-app", "app-id=com.example.app").unwrap();
+    assert_eq!(app.platform, "play");
+    assert_eq!(app.id.as_deref(), Some("com.example.app"));
+
+    assert_eq!(NativeApp::from_meta("apple-itunes-app", "app-id="), None);
+    assert_eq!(NativeApp::from_meta("viewport", "app-id=123"), None);
+  }
+
+  /// Serves an icon for every `itunes` app, and fails the `play` ones
+  struct StubResolver;
+
+  impl AppStoreResolver for StubResolver {
+    fn icon_url<'a>(
+      &'a self,
+      app: &'a NativeApp,
+    ) -> LocalBoxFuture<'a, Result<Option<Url>, Box<dyn Error>>> {
+      let result = match app.platform.as_str() {
+        "itunes" => Ok(Some(Url::parse("https://store.example/icon.png").unwrap())),
+        "play" => Err("lookup failed".into()),
+        _ => Ok(None),
+      };
+
+      ready(result).boxed_local()
+    }
+  }
+
+  fn app(platform: &str, id: &str) -> NativeApp {
+    NativeApp {
+      platform: platform.to_string(),
+      id: Some(id.to_string()),
+      url: None,
+      source: IconSource::HeadTag,
+    }
+  }
+
+  #[tokio::test]
+  async fn resolves_store_icons() {
+    let apps = [
+      app("itunes", "1"),
+      app("itunes", "2"),
+      app("play", "com.example.app"),
+      app("windows", "9WZDNCRFJ3TJ"),
+    ];
+
+    assert_eq!(SiteIcons::new().app_icon_candidates(&apps).await, vec![]);
+
+    let candidates = SiteIcons::new()
+      .with_app_store_resolver(StubResolver)
+      .app_icon_candidates(&apps)
+      .await;
+
+    assert_eq!(
+      candidates,
+      vec![IconCandidate {
+        href: Url::parse("https://store.example/icon.png").unwrap(),
+        kind: IconKind::AppIcon,
+        rel: None,
+        sizes: None,
+        mime_type: None,
+        media: None,
+        purpose: Vec::new(),
+        source: IconSource::AppStore,
+      }]
+    );
+  }
+
+  #[test]
+  fn identifies_apps_regardless_of_source() {
+    let manifest_app = NativeApp {
+      source: IconSource::Manifest(Url::parse("https://example.com/manifest.json").unwrap()),
+      ..app("itunes", "1")
+    };
+    assert_eq!(app("itunes", "1").key(), manifest_app.key());
+    assert_ne!(app("itunes", "1").key(), app("play", "1").key());
+
+    let url = Url::parse("https://apps.apple.com/app/id1").unwrap();
+    let listed_app = NativeApp {
+      id: None,
+      url: Some(url.clone()),
+      ..app("itunes", "1")
+    };
+    assert_eq!(
+      listed_app.key(),
+      ("itunes".to_string(), Some(url.to_string()))
+    );
+  }
+}
@@ -0,0 +1,82 @@
+use crate::IconKind;
+use url::Url;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProbeKind {
+  Icon(IconKind),
+  Manifest,
+}
+
+/// A well-known path that's probed for on the page directory, and on its
+/// parent directories (for sites hosted under a subpath)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Probe {
+  pub path: String,
+  pub kind: ProbeKind,
+}
+
+impl Probe {
+  pub fn icon<P: Into<String>>(path: P, kind: IconKind) -> Self {
+    Probe {
+      path: path.into(),
+      kind: ProbeKind::Icon(kind),
+    }
+  }
+
+  pub fn manifest<P: Into<String>>(path: P) -> Self {
+    Probe {
+      path: path.into(),
+      kind: ProbeKind::Manifest,
+    }
+  }
+
+  /// The paths that are probed by default
+  pub fn defaults() -> Vec<Probe> {
+    vec![
+      Probe::icon("favicon.svg", IconKind::SiteFavicon),
+      Probe::icon("favicon.ico", IconKind::SiteFavicon),
+      Probe::icon("apple-touch-icon.png", IconKind::AppIcon),
+      Probe::icon("apple-touch-icon-precomposed.png", IconKind::AppIcon),
+      Probe::icon("android-chrome-512x512.png", IconKind::AppIcon),
+      Probe::manifest("manifest.json"),
+      Probe::manifest("manifest.webmanifest"),
+      Probe::manifest("site.webmanifest"),
+    ]
+  }
+
+  /// The directories to probe in, from the deepest up to the root: the
+  /// directory of the page and its parents, at most `max_depth` directories
+  /// below the root
+  pub fn dirs(url: &Url, max_depth: usize) -> Vec<Url> {
+    let mut segments: Vec<_> = url
+      .path_segments()
+      .map(|segments| segments.collect())
+      .unwrap_or_default();
+    // the last segment is the page, unless the path ends with a slash
+    segments.pop();
+    segments.retain(|segment| !segment.is_empty());
+    segments.truncate(max_depth);
+
+    (0..=segments.len())
+      .rev()
+      .map(|depth| {
+        let mut dir = url.clone();
+        dir.set_query(None);
+        dir.set_fragment(None);
+
+        if depth == 0 {
+          dir.set_path("/");
+        } else {
+          dir.set_path(&format!("/{}/", segments[..depth].join("/")));
+        }
+
+        dir
+      })
+      .collect()
+  }
+
+  /// The URL of the probe in a directory
+  pub fn url(&self, dir: &Url) -> Option<Url> {
+    dir.join(self.path.trim_start_matches('/')).ok()
+  }
+}
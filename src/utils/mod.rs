@@ -2,15 +2,9 @@
 mod macros;
 mod background_poll;
 mod svg_encoder;
+mod text;
 
 pub use background_poll::*;
 pub use macros::*;
 pub use svg_encoder::*;
-
-use url::Url;
-
-pub fn push_url(url: &Url, segment: &str) -> Url {
-  let mut url = url.clone();
-  url.path_segments_mut().unwrap().push(segment);
-  url
-}
+pub use text::*;
@@ -0,0 +1,88 @@
+use std::ops::RangeInclusive;
+use unicode_normalization::UnicodeNormalization;
+
+/// The Latin, Greek and Cyrillic combining diacritical marks
+const COMBINING_MARKS: RangeInclusive<char> = '\u{300}'..='\u{36f}';
+
+/// Lowercases the text and strips its diacritics, so it can be compared case-
+/// and diacritic-insensitively, e.g. `Logótipo` becomes `logotipo`. Only the
+/// Latin, Greek and Cyrillic combining marks are stripped, so e.g. `ゴ` stays `ゴ`.
+pub fn fold_text(text: &str) -> String {
+  if text.is_ascii() {
+    return text.to_ascii_lowercase();
+  }
+
+  text
+    .nfkd()
+    .filter(|c| !COMBINING_MARKS.contains(c))
+    .nfc()
+    .collect::<String>()
+    .to_lowercase()
+}
+
+/// The folded words of the text, split on anything that isn't a letter and
+/// on camelCase humps, e.g. `site`, `logo`, `x` and `png` for `siteLogo@2x.png`
+pub fn fold_words(text: &str) -> Vec<String> {
+  let mut words = Vec::new();
+  let mut word = String::new();
+  let mut after_lowercase = false;
+
+  for c in text.chars() {
+    let is_mark = COMBINING_MARKS.contains(&c);
+    let in_word = c.is_alphabetic() || is_mark;
+
+    if (!in_word || (after_lowercase && c.is_uppercase())) && !word.is_empty() {
+      words.push(fold_text(&word));
+      word.clear();
+    }
+
+    if in_word {
+      word.push(c);
+    }
+    if !is_mark {
+      after_lowercase = c.is_lowercase();
+    }
+  }
+
+  if !word.is_empty() {
+    words.push(fold_text(&word));
+  }
+
+  words
+}
+
+/// The folded text with only its letters and digits, so e.g. `Acme Corp`
+/// matches `acmecorp`
+pub fn compact_text(text: &str) -> String {
+  fold_text(text)
+    .chars()
+    .filter(|c| c.is_alphanumeric())
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn folds_text() {
+    assert_eq!(fold_text("Logótipo"), "logotipo");
+    assert_eq!(fold_text("ЛОГО"), "лого");
+    assert_eq!(fold_text("ロゴ"), "ロゴ");
+  }
+
+  #[test]
+  fn splits_words() {
+    assert_eq!(fold_words("siteLogo@2x.png"), ["site", "logo", "x", "png"]);
+    assert_eq!(fold_words("main_logo-dark"), ["main", "logo", "dark"]);
+    assert_eq!(fold_words("sitelogo"), ["sitelogo"]);
+    assert_eq!(fold_words("HTMLLogo"), ["htmllogo"]);
+    assert_eq!(fold_words("Logótipo Ünïcode"), ["logotipo", "unicode"]);
+    assert_eq!(fold_words("会社のロゴ"), ["会社のロゴ"]);
+  }
+
+  #[test]
+  fn compacts_text() {
+    assert_eq!(compact_text("Acme Corp."), "acmecorp");
+  }
+}